@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use rusty_fzscheme::Interpreter;
+
+const PROGRAMS: &str = "
+(define (fib n)
+  (if (< n 2)
+      n
+      (+ (fib (- n 1)) (fib (- n 2)))))
+
+(define (tak x y z)
+  (if (< y x)
+      (tak (tak (- x 1) y z)
+           (tak (- y 1) z x)
+           (tak (- z 1) x y))
+      z))
+
+(define (count n acc)
+  (if (= n 0)
+      acc
+      (count (- n 1) (+ acc 1))))
+";
+
+fn bench_vm(c: &mut Criterion) {
+    let mut interp = Interpreter::new();
+    interp.eval_str(PROGRAMS).unwrap();
+
+    for (name, expr) in [
+        ("fib 25", "(fib 25)"),
+        ("tak 12 6 0", "(tak 12 6 0)"),
+        ("count 10000", "(count 10000 0)"),
+    ] {
+        c.bench_function(name, |b| b.iter(|| interp.eval_str(expr).unwrap()));
+    }
+}
+
+criterion_group!(benches, bench_vm);
+criterion_main!(benches);
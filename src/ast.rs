@@ -1,20 +1,51 @@
-use std::cell::RefCell;
-use std::collections::LinkedList;
+use std::cell::{Ref, RefCell};
+use std::fmt;
 use std::rc::Rc;
 
-use crate::{env::Env, inst::Inst};
+use num_bigint::BigInt;
+use num_rational::BigRational;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+use crate::{
+    env::Env,
+    error::RuntimeError,
+    inst::Lambda,
+    number,
+    symbol::Symbol,
+    syntax_rules::{SyntaxEnv, SyntaxRules},
+    vm::ContinuationData,
+};
+
+/// `#\space` のように名前で書ける文字
+pub const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("null", '\0'),
+    ("alarm", '\x07'),
+    ("backspace", '\x08'),
+    ("escape", '\x1b'),
+    ("delete", '\x7f'),
+];
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Node {
     Bool(bool),
-    List(Vec<Node>),
+    Nil,
+    Pair(Rc<Pair>),
     Int(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
+    Real(f64),
     Str(String),
-    Ident(String),
-    Primitive(String, fn(Vec<Node>) -> Node),
-    Closure(LinkedList<Inst>, Rc<RefCell<Env>>),
-    Macro(LinkedList<Inst>),
-    Error(String),
+    Char(char),
+    Ident(Symbol),
+    Primitive(Rc<Primitive>),
+    Closure(Rc<Lambda>, Rc<RefCell<Env>>),
+    Macro(Rc<Lambda>),
+    Syntax(Rc<SyntaxRules>),
+    Renamed(Box<Node>, usize, SyntaxEnv),
+    Continuation(Rc<ContinuationData>),
     Undef,
 }
 
@@ -22,38 +53,288 @@ pub enum Node {
 pub enum ProcTag {
     Primitive,
     Closure,
+    Continuation,
+}
+
+/// 手続きが受け付ける引数の個数
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    Range(usize, usize),
+}
+
+impl Arity {
+    /// lambda 式の仮引数リストから引数の個数を求める
+    pub fn of_params(params: &Node) -> Self {
+        let (required, rest) = params.list_parts();
+        if rest.is_null() {
+            Arity::Exact(required.len())
+        } else {
+            Arity::AtLeast(required.len())
+        }
+    }
+
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exact(num) => n == num,
+            Arity::AtLeast(min) => min <= n,
+            Arity::Range(min, max) => min <= n && n <= max,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Exact(num) => write!(f, "{}", num),
+            Arity::AtLeast(min) => write!(f, "at least {}", min),
+            Arity::Range(min, max) => write!(f, "{} to {}", min, max),
+        }
+    }
+}
+
+/// 組み込み手続きの本体
+pub type PrimitiveFn = dyn Fn(&[Node]) -> Result<Node, RuntimeError>;
+
+/// VM が直接処理する、実行の流れを変える組み込み手続きの種類
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Control {
+    CallCc,
+}
+
+enum PrimitiveBody {
+    Func(Box<PrimitiveFn>),
+    Control(Control),
+}
+
+/// 組み込み手続き。Rust の関数やクロージャを Scheme の手続きとして扱う
+pub struct Primitive {
+    pub name: String,
+    pub arity: Arity,
+    body: PrimitiveBody,
+}
+
+impl Primitive {
+    pub fn new<F>(name: &str, arity: Arity, func: F) -> Self
+    where
+        F: Fn(&[Node]) -> Result<Node, RuntimeError> + 'static,
+    {
+        Primitive {
+            name: name.to_string(),
+            arity,
+            body: PrimitiveBody::Func(Box::new(func)),
+        }
+    }
+
+    /// VM が直接処理する組み込み手続きを作る
+    pub fn control(name: &str, arity: Arity, control: Control) -> Self {
+        Primitive {
+            name: name.to_string(),
+            arity,
+            body: PrimitiveBody::Control(control),
+        }
+    }
+
+    pub fn as_control(&self) -> Option<Control> {
+        match self.body {
+            PrimitiveBody::Func(_) => None,
+            PrimitiveBody::Control(control) => Some(control),
+        }
+    }
+
+    pub fn call(&self, args: &[Node]) -> Result<Node, RuntimeError> {
+        match &self.body {
+            PrimitiveBody::Func(func) => func(args),
+            PrimitiveBody::Control(_) => Err(RuntimeError::Primitive(format!(
+                "{}: must be applied by the VM",
+                self.name
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for Primitive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Primitive")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+// 関数は比較できないので、同じ実体かどうかで比べる
+impl PartialEq for Primitive {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// 変更可能なコンスセル
+#[derive(Debug, PartialEq)]
+pub struct Pair {
+    car: RefCell<Node>,
+    cdr: RefCell<Node>,
+}
+
+impl Pair {
+    /// car と cdr を複製せずに参照する
+    pub fn fields(&self) -> (Ref<'_, Node>, Ref<'_, Node>) {
+        (self.car.borrow(), self.cdr.borrow())
+    }
+
+    /// car と cdr を空リストにして、参照しているオブジェクトを手放す
+    pub fn clear(&self) {
+        *self.car.borrow_mut() = Node::Nil;
+        *self.cdr.borrow_mut() = Node::Nil;
+    }
+}
+
+// 長いリストを再帰的に解放するとスタックが溢れるので、
+// 他から参照されていない cdr の連なりはループでたどって解放する
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut next = std::mem::replace(self.cdr.get_mut(), Node::Nil);
+        while let Node::Pair(pair) = next {
+            match Rc::try_unwrap(pair) {
+                Ok(mut pair) => next = std::mem::replace(pair.cdr.get_mut(), Node::Nil),
+                Err(_) => break,
+            }
+        }
+    }
 }
 
 impl Node {
-    pub fn is_list(&self) -> bool {
+    pub fn cons(car: Node, cdr: Node) -> Self {
+        Node::Pair(Rc::new(Pair {
+            car: RefCell::new(car),
+            cdr: RefCell::new(cdr),
+        }))
+    }
+
+    /// 要素の列から真リストを作る
+    pub fn list(items: Vec<Node>) -> Self {
+        Node::list_with_tail(items, Node::nil())
+    }
+
+    /// 要素の列と末尾から（末尾が nil でなければ不完全な）リストを作る
+    pub fn list_with_tail(items: Vec<Node>, tail: Node) -> Self {
+        items
+            .into_iter()
+            .rev()
+            .fold(tail, |cdr, car| Node::cons(car, cdr))
+    }
+
+    pub fn car(&self) -> Option<Node> {
         match self {
-            Node::List(nodes) => {
-                if let Some(last) = nodes.last() {
-                    last.is_null()
-                } else {
-                    true
-                }
+            Node::Pair(pair) => Some(pair.car.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    pub fn cdr(&self) -> Option<Node> {
+        match self {
+            Node::Pair(pair) => Some(pair.cdr.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set_car(&self, val: Node) -> Option<()> {
+        match self {
+            Node::Pair(pair) => {
+                *pair.car.borrow_mut() = val;
+                Some(())
             }
-            _ => false,
+            _ => None,
         }
     }
 
-    pub fn is_null(&self) -> bool {
+    pub fn set_cdr(&self, val: Node) -> Option<()> {
         match self {
-            Node::List(nodes) => nodes.is_empty(),
-            _ => false,
+            Node::Pair(pair) => {
+                *pair.cdr.borrow_mut() = val;
+                Some(())
+            }
+            _ => None,
+        }
+    }
+
+    /// リストを要素の列と末尾（真リストなら nil）に分解する
+    pub fn list_parts(&self) -> (Vec<Node>, Node) {
+        let mut items = Vec::new();
+        let mut node = self.clone();
+        while let Node::Pair(pair) = node {
+            items.push(pair.car.borrow().clone());
+            let next = pair.cdr.borrow().clone();
+            node = next;
+        }
+        (items, node)
+    }
+
+    /// 真リストであれば要素の列を返す
+    pub fn to_vec(&self) -> Option<Vec<Node>> {
+        let (items, tail) = self.list_parts();
+        if tail.is_null() {
+            Some(items)
+        } else {
+            None
         }
     }
 
+    /// 先頭から `n` 個目の cdr を返す
+    pub fn nth_cdr(&self, n: usize) -> Option<Node> {
+        let mut node = self.clone();
+        for _ in 0..n {
+            node = node.cdr()?;
+        }
+        Some(node)
+    }
+
+    pub fn is_list(&self) -> bool {
+        let mut node = self.clone();
+        while let Node::Pair(pair) = node {
+            let next = pair.cdr.borrow().clone();
+            node = next;
+        }
+        node.is_null()
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Node::Nil)
+    }
+
     pub fn is_pair(&self) -> bool {
+        matches!(self, Node::Pair(_))
+    }
+
+    /// ペアであればセルを識別する値を返す
+    pub fn pair_id(&self) -> Option<usize> {
         match self {
-            Node::List(nodes) => !nodes.is_empty(),
-            _ => false,
+            Node::Pair(pair) => Some(Rc::as_ptr(pair) as usize),
+            _ => None,
         }
     }
 
     pub fn nil() -> Self {
-        Node::List(Vec::new())
+        Node::Nil
+    }
+
+    /// 名前を登録してシンボルを作る
+    pub fn ident(name: &str) -> Self {
+        Node::Ident(Symbol::intern(name))
+    }
+
+    /// `eq?` の意味での同一性。ペアと手続きは同じ実体であるときに限り等しい
+    pub fn is_eq(&self, other: &Node) -> bool {
+        match (self, other) {
+            (Node::Pair(a), Node::Pair(b)) => Rc::ptr_eq(a, b),
+            // 環境は自身を参照する手続きを含みうるので、中身は比べない
+            (Node::Closure(code_a, env_a), Node::Closure(code_b, env_b)) => {
+                Rc::ptr_eq(code_a, code_b) && Rc::ptr_eq(env_a, env_b)
+            }
+            (Node::Continuation(a), Node::Continuation(b)) => Rc::ptr_eq(a, b),
+            _ => self == other,
+        }
     }
 
     pub fn inspect(&self) -> String {
@@ -66,33 +347,73 @@ impl Node {
                 }
             }
             Node::Int(int) => int.to_string(),
+            Node::BigInt(int) => int.to_string(),
+            Node::Rational(rat) => rat.to_string(),
+            Node::Real(real) => number::format_real(*real),
             Node::Str(string) => format!("{:?}", string),
-            Node::Ident(ident) => ident.clone(),
-            Node::List(nodes) => {
-                if nodes.is_empty() {
-                    return "()".to_string();
-                }
-                let mut s = String::from("(");
-                for (i, node) in nodes.iter().enumerate() {
-                    if i == nodes.len() - 1 {
-                        if node.is_null() {
-                            break;
-                        } else {
-                            s += " .";
-                        }
-                    }
-                    if i != 0 {
-                        s += " ";
-                    }
-                    s += &node.inspect();
+            Node::Char(ch) => inspect_char(*ch),
+            Node::Ident(ident) => ident.to_string(),
+            Node::Nil => "()".to_string(),
+            Node::Pair(_) => {
+                let (items, tail) = self.list_parts();
+                let items: Vec<String> = items.iter().map(|item| item.inspect()).collect();
+                if tail.is_null() {
+                    format!("({})", items.join(" "))
+                } else {
+                    format!("({} . {})", items.join(" "), tail.inspect())
                 }
-                s + ")"
             }
-            Node::Primitive(name, _) => format!("#<primitive {}>", name),
-            Node::Closure(code, _) => format!("#<closure {:?}>", code),
-            Node::Macro(code) => format!("#<macro {:?}", code),
-            Node::Error(msg) => format!("Error: {}", msg),
+            Node::Primitive(prim) => format!("#<primitive {}>", prim.name),
+            Node::Closure(lambda, _) => format!("#<closure {}>", lambda.params.inspect()),
+            Node::Macro(lambda) => format!("#<macro {}>", lambda.params.inspect()),
+            Node::Syntax(_) => "#<syntax>".to_string(),
+            Node::Renamed(base, _, _) => base.inspect(),
+            Node::Continuation(_) => "#<continuation>".to_string(),
             Node::Undef => "#<undef>".to_string(),
         }
     }
 }
+
+/// 文字を読み戻せる `#\` 記法で表す。名前のある文字と制御文字は名前か `#\x` 記法にする
+fn inspect_char(ch: char) -> String {
+    if let Some((name, _)) = CHAR_NAMES.iter().find(|(_, named)| *named == ch) {
+        format!("#\\{}", name)
+    } else if ch.is_control() {
+        format!("#\\x{:x}", ch as u32)
+    } else {
+        format!("#\\{}", ch)
+    }
+}
+
+#[cfg(test)]
+mod ast_test {
+    use super::Node;
+    use crate::{lexer::Lexer, parser::Parser};
+
+    #[test]
+    fn inspect_list_test() {
+        let source = "(() (a) (a b . c) (a (b c) . ()) (a . (b . (c))))";
+        let expected = "(() (a) (a b . c) (a (b c)) (a b c))";
+        let node = Parser::new(Lexer::new(source)).parse().unwrap().remove(0);
+
+        assert_eq!(node.inspect(), expected);
+    }
+
+    #[test]
+    fn inspect_char_test() {
+        assert_eq!(Node::Char('a').inspect(), "#\\a");
+        assert_eq!(Node::Char(' ').inspect(), "#\\space");
+        assert_eq!(Node::Char('\n').inspect(), "#\\newline");
+        assert_eq!(Node::Char('\x01').inspect(), "#\\x1");
+        assert_eq!(Node::Char('あ').inspect(), "#\\あ");
+    }
+
+    #[test]
+    fn drop_long_list_test() {
+        let list = Node::list((0..1_000_000).map(Node::Int).collect());
+        let shared = list.cdr().unwrap();
+        drop(list);
+        assert_eq!(shared.car(), Some(Node::Int(1)));
+        drop(shared);
+    }
+}
@@ -1,341 +1,578 @@
 use std::cell::RefCell;
-use std::collections::LinkedList;
 use std::rc::Rc;
 
 use crate::{
-    ast::Node,
-    env::{Env, GlobalEnv},
-    inst::Inst,
-    vm::{DumpItem, DumpStack, StackItem, StackStack, VM},
+    ast::{Arity, Node},
+    env::{env_depth, Env, GlobalEnv, LocalBinding},
+    error::CompileError,
+    inst::{Inst, Lambda},
+    span::{Located, Span, SpanTable},
+    symbol::Symbol,
+    syntax_rules::{is_identifier, strip, SyntaxRules},
+    vm::{DumpItem, DumpStack, StackItem, VM},
 };
 
-pub struct Compiler {
+pub type CompileResult<T> = Result<T, Located<CompileError>>;
+
+/// コンパイラが直接扱う特殊形式などのキーワード
+pub const SPECIAL_FORMS: &[&str] = &[
+    "quote",
+    "if",
+    "lambda",
+    "define",
+    "define-macro",
+    "define-syntax",
+    "let-syntax",
+    "letrec-syntax",
+    "set!",
+    "syntax-rules",
+];
+
+pub struct Compiler<'a> {
     node: Node,
+    spans: Option<&'a SpanTable>,
 }
 
-impl Compiler {
+impl<'a> Compiler<'a> {
     pub fn new(node: Node) -> Self {
-        Compiler { node }
+        Compiler { node, spans: None }
     }
 
-    pub fn compile(self, global_env: &mut GlobalEnv) -> Result<LinkedList<Inst>, &'static str> {
-        let mut stop_code = LinkedList::new();
-        stop_code.push_back(Inst::Stop);
+    /// 構文解析で得た位置情報とともにコンパイルする。
+    /// 生成するコードには式の位置を示す `Inst::Loc` が挿入される
+    pub fn with_spans(node: Node, spans: &'a SpanTable) -> Self {
+        Compiler {
+            node,
+            spans: Some(spans),
+        }
+    }
+
+    pub fn compile(self, global_env: &mut GlobalEnv) -> CompileResult<Vec<Inst>> {
+        let empty = SpanTable::new();
+        let loc = Locator {
+            spans: self.spans.unwrap_or(&empty),
+            span: None,
+        };
+        let mut code = Vec::new();
         compile_expr(
             self.node,
             Rc::new(RefCell::new(Env::new())),
             global_env,
-            &mut stop_code,
-        )
+            loc,
+            false,
+            &mut code,
+        )?;
+        code.push(Inst::Stop);
+        Ok(code)
+    }
+}
+
+/// コンパイル中の式を囲む、位置の分かっている最も内側の式の位置
+#[derive(Clone, Copy)]
+struct Locator<'a> {
+    spans: &'a SpanTable,
+    span: Option<Span>,
+}
+
+impl<'a> Locator<'a> {
+    fn enter(self, expr: &Node) -> Self {
+        Locator {
+            spans: self.spans,
+            span: self.spans.get(expr).or(self.span),
+        }
     }
 }
 
+/// 式をコンパイルして `code` の末尾に追加する。`tail` が真であれば式は末尾位置にあり、
+/// その値で手続きから戻る。位置の分かっている式であれば、その評価の前に位置を記録し、
+/// 評価の後で外側の式の位置に戻す命令を挿入する。
 fn compile_expr(
     expr: Node,
     env: Rc<RefCell<Env>>,
     global_env: &mut GlobalEnv,
-    code: &mut LinkedList<Inst>,
-) -> Result<LinkedList<Inst>, &'static str> {
-    let mut new_code = LinkedList::new();
-    match expr {
-        Node::Bool(_) | Node::Int(_) | Node::Str(_) => {
-            new_code.push_back(Inst::Ldc(expr));
-            new_code.append(code);
-            Ok(new_code)
+    loc: Locator,
+    tail: bool,
+    code: &mut Vec<Inst>,
+) -> CompileResult<()> {
+    let outer = loc.span;
+    let loc = loc.enter(&expr);
+    if loc.span == outer {
+        return compile_form(expr, env, global_env, loc, tail, code)
+            .map_err(|err| err.or_span(outer));
+    }
+    if let Some(span) = loc.span {
+        code.push(Inst::Loc(span));
+    }
+    compile_form(expr, env, global_env, loc, tail, code).map_err(|err| err.or_span(loc.span))?;
+    if let Some(outer) = outer {
+        if !tail {
+            code.push(Inst::Loc(outer));
         }
-        Node::Ident(_) => {
-            if let Some(pos) = env.borrow().location(&expr) {
-                new_code.push_back(Inst::Ld(pos.0, pos.1));
-                new_code.append(code);
-                Ok(new_code)
-            } else {
-                new_code.push_back(Inst::Ldg(expr));
-                new_code.append(code);
-                Ok(new_code)
+    }
+    Ok(())
+}
+
+fn compile_form(
+    expr: Node,
+    env: Rc<RefCell<Env>>,
+    global_env: &mut GlobalEnv,
+    loc: Locator,
+    tail: bool,
+    code: &mut Vec<Inst>,
+) -> CompileResult<()> {
+    match expr {
+        Node::Bool(_)
+        | Node::Int(_)
+        | Node::BigInt(_)
+        | Node::Rational(_)
+        | Node::Real(_)
+        | Node::Str(_)
+        | Node::Char(_) => code.push(Inst::Ldc(expr)),
+        Node::Ident(_) | Node::Renamed(_, _, _) => match resolve(&expr, &env, global_env)? {
+            Ref::Local(i, j) => code.push(Inst::Ld(i, j)),
+            Ref::Global(sym) => code.push(Inst::Ldg(sym)),
+            Ref::Syntax(_) => {
+                return Err(CompileError::SyntaxAsVariable(strip(&expr).inspect()).into())
             }
-        }
-        Node::List(nodes) => {
-            if let Some(fst) = nodes.first() {
-                match fst {
-                    Node::Ident(ident) => {
-                        if ident == "quote" {
-                            if let Some(snd) = nodes.get(1) {
-                                new_code.push_back(Inst::Ldc(snd.clone()));
-                                new_code.append(code);
-                                return Ok(new_code);
-                            } else {
-                                return Err("shortage of the args of `quote`.");
-                            }
-                        } else if ident == "if" {
-                            let mut nodes = nodes.clone();
-                            // 末尾の nil を削除
-                            nodes.pop();
-                            let second = nodes.get(1);
-                            let third = nodes.get(2);
-                            let forth = nodes.get(3);
-                            if second.is_none() || third.is_none() {
-                                return Err("shortage of the args of `if`.");
+        },
+        Node::Nil => return Err(CompileError::EmptyApplication.into()),
+        Node::Pair(_) => {
+            let nodes = match expr.to_vec() {
+                Some(nodes) => nodes,
+                None => return Err(CompileError::ImproperList.into()),
+            };
+            // nodes は必ず 1 要素以上持っている
+            let fst = &nodes[0];
+            let head = if is_identifier(fst) {
+                Some(resolve(fst, &env, global_env)?)
+            } else {
+                None
+            };
+            match &head {
+                Some(Ref::Global(Symbol::QUOTE)) => {
+                    if let Some(snd) = nodes.get(1) {
+                        code.push(Inst::Ldc(strip(snd)));
+                    } else {
+                        return Err(CompileError::ShortageOfArgs("quote").into());
+                    }
+                }
+                Some(Ref::Global(Symbol::IF)) => {
+                    if nodes.get(1).is_none() || nodes.get(2).is_none() {
+                        return Err(CompileError::ShortageOfArgs("if").into());
+                    }
+                    compile_expr(nodes[1].clone(), env.clone(), global_env, loc, false, code)?;
+                    let jf = code.len();
+                    code.push(Inst::Jf(0));
+                    compile_expr(nodes[2].clone(), env.clone(), global_env, loc, tail, code)?;
+                    // 末尾位置では各節が Rtn で終わるので、節の後に合流する必要はない
+                    let jmp = code.len();
+                    if !tail {
+                        code.push(Inst::Jmp(0));
+                    }
+                    code[jf] = Inst::Jf(code.len());
+                    if let Some(forth) = nodes.get(3) {
+                        compile_expr(forth.clone(), env, global_env, loc, tail, code)?;
+                    } else {
+                        code.push(Inst::Ldc(Node::Undef));
+                        if tail {
+                            code.push(Inst::Rtn);
+                        }
+                    }
+                    if !tail {
+                        code[jmp] = Inst::Jmp(code.len());
+                    }
+                    return Ok(());
+                }
+                Some(Ref::Global(Symbol::LAMBDA)) => {
+                    let mut body = nodes.clone();
+                    body.remove(0);
+                    if body.is_empty() || body.get(1).is_none() {
+                        return Err(CompileError::ShortageOfArgs("lambda").into());
+                    }
+                    let params = body.remove(0);
+
+                    let arity = Arity::of_params(&params);
+                    let new_env = Rc::new(RefCell::new(Env::new()));
+                    new_env.borrow_mut().set_node(params.clone());
+                    new_env.borrow_mut().set_next_env(env);
+
+                    let lambda =
+                        compile_lambda(body, new_env, global_env, loc, arity, strip(&params))?;
+                    code.push(Inst::Ldf(lambda));
+                }
+                Some(Ref::Global(Symbol::DEFINE)) => {
+                    let second = nodes.get(1);
+                    let third = nodes.get(2);
+                    if second.is_none() || third.is_none() {
+                        return Err(CompileError::ShortageOfArgs("define").into());
+                    }
+                    let mut second = nodes.get(1).unwrap().clone();
+                    let mut third = nodes.get(2).unwrap().clone();
+
+                    match second.clone() {
+                        Node::Ident(_) | Node::Renamed(_, _, _) => (),
+                        Node::Pair(_) => {
+                            // (define (name arg ...) body ...) を
+                            // (define name (lambda (arg ...) body ...)) に解釈し直す
+                            let proc_name = second.car().unwrap();
+                            if !is_identifier(&proc_name) {
+                                return Err(CompileError::BadDefineTarget.into());
                             }
-                            let mut join_code = LinkedList::new();
-                            join_code.push_back(Inst::Join);
-                            let t_clause = compile_expr(
-                                third.unwrap().clone(),
-                                env.clone(),
-                                global_env,
-                                &mut join_code,
-                            )?;
-                            let f_clause = if forth.is_none() {
-                                let mut join_code = LinkedList::new();
-                                join_code.push_back(Inst::Ldc(Node::Undef));
-                                join_code.push_back(Inst::Join);
-                                join_code
-                            } else {
-                                let mut join_code = LinkedList::new();
-                                join_code.push_back(Inst::Join);
-                                compile_expr(
-                                    forth.unwrap().clone(),
-                                    env.clone(),
-                                    global_env,
-                                    &mut join_code,
-                                )?
-                            };
-                            new_code.push_back(Inst::Sel(t_clause, f_clause));
-                            new_code.append(code);
-                            return compile_expr(
-                                second.unwrap().clone(),
-                                env,
-                                global_env,
-                                &mut new_code,
-                            );
-                        } else if ident == "lambda" {
+                            let args = second.cdr().unwrap();
+                            second = proc_name;
+
+                            let mut lambda_node_list = Vec::new();
+                            lambda_node_list.push(Node::Ident(Symbol::LAMBDA));
+                            lambda_node_list.push(args);
                             let mut body = nodes.clone();
                             body.remove(0);
-                            if body.get(0).is_none() || body.get(1).is_none() {
-                                return Err("shortage of the args of `lambda`.");
-                            }
-                            let args = body.remove(0);
-
-                            let new_env = Rc::new(RefCell::new(Env::new()));
-                            new_env.borrow_mut().set_node(args);
-                            new_env.borrow_mut().set_next_env(env);
-
-                            let mut rtn_code = LinkedList::new();
-                            rtn_code.push_back(Inst::Rtn);
-                            let body = compile_body(body, new_env, global_env, &mut rtn_code)?;
-                            new_code.push_back(Inst::Ldf(body));
-                            new_code.append(code);
-                            return Ok(new_code);
-                        } else if ident == "define" {
-                            let second = nodes.get(1);
-                            let third = nodes.get(2);
-                            if second.is_none() || third.is_none() {
-                                return Err("shortage of the args of `define`.");
-                            }
-                            let mut second = nodes.get(1).unwrap().clone();
-                            let mut third = nodes.get(2).unwrap().clone();
-
-                            match second.clone() {
-                                Node::Ident(_) => (),
-                                Node::List(mut define_fst_list) => {
-                                    // (define (name arg ...) body ...) を
-                                    // (define name (lambda (arg ...) body ...)) に解釈し直す
-                                    if define_fst_list.get(0).is_none() {
-                                        return Err(
-                                            "proc name not found in `define` first argument.",
-                                        );
-                                    }
-                                    let proc_name = define_fst_list.remove(0);
-                                    second = proc_name;
-
-                                    let mut lambda_node_list = Vec::new();
-                                    lambda_node_list.push(Node::Ident("lambda".to_string()));
-                                    lambda_node_list.push(Node::List(define_fst_list));
-                                    let mut body = nodes.clone();
-                                    body.remove(0);
-                                    body.remove(0);
-                                    lambda_node_list.extend(body);
-                                    third = Node::List(lambda_node_list);
-                                }
-                                _ => {
-                                    return Err(
-                                        "can accept only symbol or list as first arg of `define`.",
-                                    );
-                                }
-                            }
+                            body.remove(0);
+                            lambda_node_list.extend(body);
+                            third = Node::list(lambda_node_list);
+                        }
+                        _ => {
+                            return Err(CompileError::BadDefineTarget.into());
+                        }
+                    }
 
-                            new_code.push_back(Inst::Def(second));
-                            new_code.append(code);
-                            return compile_expr(third, env, global_env, &mut new_code);
-                        } else if ident == "define-macro" {
-                            let second = nodes.get(1);
-                            let third = nodes.get(2);
-                            if second.is_none() || third.is_none() {
-                                return Err("shortage of the args of `define-macro`.");
-                            }
-                            let second = nodes.get(1).unwrap().clone();
-                            let third = nodes.get(2).unwrap().clone();
-
-                            match second {
-                                Node::Ident(_) => (),
-                                _ => {
-                                    return Err("can accept only symbol as first arg of `define-macro` currently.");
-                                }
-                            }
+                    let start = code.len();
+                    compile_expr(third, env, global_env, loc, false, code)?;
+                    // 値が lambda 式そのものなら、作られる手続きに名前を付ける
+                    if let [Inst::Ldf(lambda)] = &mut code[start..] {
+                        if let Some(lambda) = Rc::get_mut(lambda) {
+                            lambda.name = Some(symbol_of(&second));
+                        }
+                    }
+                    code.push(Inst::Def(symbol_of(&second)));
+                }
+                Some(Ref::Global(Symbol::DEFINE_MACRO)) => {
+                    let second = nodes.get(1);
+                    let third = nodes.get(2);
+                    if second.is_none() || third.is_none() {
+                        return Err(CompileError::ShortageOfArgs("define-macro").into());
+                    }
+                    let second = nodes.get(1).unwrap().clone();
+                    let third = nodes.get(2).unwrap().clone();
 
-                            new_code.push_back(Inst::Defm(second));
-                            new_code.append(code);
-                            return compile_expr(third, env, global_env, &mut new_code);
-                        } else if ident == "set!" {
-                            if nodes.get(1).is_none() || nodes.get(2).is_none() {
-                                return Err("shortage of the args of `set!`.");
-                            }
-                            if let Some((i, j)) = env.borrow().location(&nodes[1]) {
-                                new_code.push_back(Inst::Lset(i, j));
-                                new_code.append(code);
-                                return compile_expr(
-                                    nodes[2].clone(),
-                                    env.clone(),
-                                    global_env,
-                                    &mut new_code,
-                                );
-                            } else {
-                                new_code.push_back(Inst::Gset(nodes[1].clone()));
-                                new_code.append(code);
-                                return compile_expr(
-                                    nodes[2].clone(),
-                                    env.clone(),
+                    if !is_identifier(&second) {
+                        return Err(CompileError::NotIdentifier("define-macro").into());
+                    }
+
+                    compile_expr(third, env, global_env, loc, false, code)?;
+                    code.push(Inst::Defm(symbol_of(&second)));
+                }
+                Some(Ref::Global(Symbol::SET)) => {
+                    if nodes.get(1).is_none() || nodes.get(2).is_none() {
+                        return Err(CompileError::ShortageOfArgs("set!").into());
+                    }
+                    if !is_identifier(&nodes[1]) {
+                        return Err(CompileError::NotIdentifier("set!").into());
+                    }
+                    let set_inst = match resolve(&nodes[1], &env, global_env)? {
+                        Ref::Local(i, j) => Inst::Lset(i, j),
+                        Ref::Global(sym) => Inst::Gset(sym),
+                        Ref::Syntax(_) => {
+                            return Err(CompileError::SetSyntax(strip(&nodes[1]).inspect()).into())
+                        }
+                    };
+                    compile_expr(nodes[2].clone(), env, global_env, loc, false, code)?;
+                    code.push(set_inst);
+                }
+                Some(Ref::Global(Symbol::DEFINE_SYNTAX)) => {
+                    if nodes.get(1).is_none() || nodes.get(2).is_none() {
+                        return Err(CompileError::ShortageOfArgs("define-syntax").into());
+                    }
+                    if !is_identifier(&nodes[1]) {
+                        return Err(CompileError::NotIdentifier("define-syntax").into());
+                    }
+                    // define と同様に大域的な定義となるので、展開時の自由な識別子も大域環境から探す
+                    let rules = compile_syntax_rules(
+                        &nodes[2],
+                        &env,
+                        Rc::new(RefCell::new(Env::new())),
+                        global_env,
+                    )?;
+                    code.push(Inst::Ldc(rules));
+                    code.push(Inst::Def(symbol_of(&nodes[1])));
+                }
+                Some(Ref::Global(ident @ (Symbol::LET_SYNTAX | Symbol::LETREC_SYNTAX))) => {
+                    if nodes.get(1).is_none() || nodes.get(2).is_none() {
+                        let form = if *ident == Symbol::LETREC_SYNTAX {
+                            "letrec-syntax"
+                        } else {
+                            "let-syntax"
+                        };
+                        return Err(CompileError::ShortageOfArgs(form).into());
+                    }
+                    // 本体は ((lambda () body ...)) と同様に新しいフレームでコンパイルし、
+                    // そのフレームに局所マクロを束縛する
+                    let new_env = Rc::new(RefCell::new(Env::new()));
+                    new_env.borrow_mut().set_next_env(env.clone());
+                    let def_env = if *ident == Symbol::LETREC_SYNTAX {
+                        new_env.clone()
+                    } else {
+                        env.clone()
+                    };
+                    let bindings = match nodes[1].to_vec() {
+                        Some(bindings) => bindings,
+                        None => return Err(CompileError::BadSyntaxBinding.into()),
+                    };
+                    for binding in bindings {
+                        match binding.to_vec() {
+                            Some(binding) if binding.len() == 2 && is_identifier(&binding[0]) => {
+                                let rules = compile_syntax_rules(
+                                    &binding[1],
+                                    &env,
+                                    def_env.clone(),
                                     global_env,
-                                    &mut new_code,
-                                );
+                                )?;
+                                new_env.borrow_mut().add_syntax(binding[0].clone(), rules);
                             }
-                        } else if let Some(macro_code) = get_macro_code(fst, global_env) {
-                            let mut vm_ = VM::new(macro_code.clone());
-
-                            let mut macro_env = Env::new();
-                            let mut macro_lvar = nodes.clone();
-                            macro_lvar.remove(0);
-                            macro_env.set_node(Node::List(macro_lvar));
-                            vm_.set_env(Rc::new(RefCell::new(macro_env)));
-
-                            let mut macro_dump = DumpStack::new();
-                            let mut dump_code = LinkedList::new();
-                            dump_code.push_back(Inst::Stop);
-                            macro_dump.push(DumpItem::new(
-                                StackStack::new(),
-                                Rc::new(RefCell::new(Env::new())),
-                                dump_code,
-                            ));
-                            vm_.set_dump(macro_dump);
-
-                            let macro_result = vm_.run(global_env);
-                            return compile_expr(macro_result, env, global_env, code);
+                            _ => return Err(CompileError::BadSyntaxBinding.into()),
                         }
                     }
-                    _ => (),
-                }
 
-                let length = if nodes.last() == Some(&Node::nil()) {
-                    nodes.len() - 2
-                } else {
-                    nodes.len() - 1
-                };
-                new_code.push_back(Inst::Args(length));
-                let mut app_code = LinkedList::new();
-                app_code.push_back(Inst::App);
-                app_code.append(code);
-                new_code.append(&mut compile_expr(
-                    fst.clone(),
-                    env.clone(),
-                    global_env,
-                    &mut app_code,
-                )?);
-                let mut nodes_clone = nodes.clone();
-                // nodes は必ず 1 要素以上持っている
-                nodes_clone.remove(0);
-                Ok(compile_list(
-                    Node::List(nodes_clone),
-                    env,
-                    global_env,
-                    &mut new_code,
-                )?)
-            } else {
-                return Err("attempt to evaluate nil.");
+                    let mut body = nodes.clone();
+                    body.drain(..2);
+                    let lambda = compile_lambda(
+                        body,
+                        new_env,
+                        global_env,
+                        loc,
+                        Arity::Exact(0),
+                        Node::nil(),
+                    )?;
+                    code.push(Inst::Args(0));
+                    code.push(Inst::Ldf(lambda));
+                    code.push(if tail { Inst::TApp } else { Inst::App });
+                }
+                Some(Ref::Syntax(transformer)) => {
+                    let expanded = expand_macro(transformer, &expr, global_env)?;
+                    return compile_expr(expanded, env, global_env, loc, tail, code);
+                }
+                _ => {
+                    for arg in &nodes[1..] {
+                        compile_expr(arg.clone(), env.clone(), global_env, loc, false, code)?;
+                    }
+                    code.push(Inst::Args(nodes.len() - 1));
+                    compile_expr(fst.clone(), env, global_env, loc, false, code)?;
+                    code.push(if tail { Inst::TApp } else { Inst::App });
+                }
             }
         }
-        _ => unreachable!("compiler treat only bool, int, ident and list objects."),
+        _ => return Err(CompileError::InvalidExpr(expr.inspect()).into()),
+    }
+    if tail {
+        code.push(Inst::Rtn);
     }
+    Ok(())
 }
 
-fn compile_list(
-    expr: Node,
+/// 手続きの本体を新しい命令列にコンパイルする
+fn compile_lambda(
+    body: Vec<Node>,
     env: Rc<RefCell<Env>>,
     global_env: &mut GlobalEnv,
-    code: &mut LinkedList<Inst>,
-) -> Result<LinkedList<Inst>, &'static str> {
-    if let Node::List(nodes) = expr {
-        if nodes.is_empty() || nodes == vec![Node::nil()] {
-            Ok(code.clone())
-        } else {
-            let mut nodes_clone = nodes.clone();
-            nodes_clone.remove(0);
-            let mut compiled_list =
-                compile_list(Node::List(nodes_clone), env.clone(), global_env, code)?;
-            compile_expr(
-                nodes.first().unwrap().clone(),
-                env,
-                global_env,
-                &mut compiled_list,
-            )
-        }
-    } else {
-        unreachable!("compile_list treat only list object.");
-    }
+    loc: Locator,
+    arity: Arity,
+    params: Node,
+) -> CompileResult<Rc<Lambda>> {
+    let mut code = Vec::new();
+    compile_body(body, env, global_env, loc, &mut code)?;
+    Ok(Rc::new(Lambda {
+        code: code.into(),
+        arity,
+        params,
+        name: None,
+    }))
 }
 
+/// 本体の式を順にコンパイルし、最後の式の値で手続きから戻る
 fn compile_body(
     body: Vec<Node>,
     env: Rc<RefCell<Env>>,
     global_env: &mut GlobalEnv,
-    code: &mut LinkedList<Inst>,
-) -> Result<LinkedList<Inst>, &'static str> {
-    if body.is_empty() {
-        unreachable!("prevent body to be empty by following code");
+    loc: Locator,
+    code: &mut Vec<Inst>,
+) -> CompileResult<()> {
+    let (last, init) = match body.split_last() {
+        Some(parts) => parts,
+        None => unreachable!("prevent body to be empty by following code"),
+    };
+    for expr in init {
+        compile_expr(expr.clone(), env.clone(), global_env, loc, false, code)?;
+        code.push(Inst::Pop);
     }
-    let mut body = body.clone();
-    let fst = body.remove(0);
-    if body.is_empty() || body == vec![Node::nil()] {
-        compile_expr(fst, env, global_env, code)
-    } else {
-        let mut pop_code = LinkedList::new();
-        pop_code.push_back(Inst::Pop);
-        pop_code.append(&mut compile_body(body, env.clone(), global_env, code)?);
-        compile_expr(fst, env, global_env, &mut pop_code)
+    compile_expr(last.clone(), env, global_env, loc, true, code)
+}
+
+/// 識別子の参照先
+enum Ref {
+    Local(usize, isize),
+    Global(Symbol),
+    Syntax(Node),
+}
+
+/// マクロ展開で導入された識別子であっても、元の名前のシンボルを返す
+fn symbol_of(ident: &Node) -> Symbol {
+    match strip(ident) {
+        Node::Ident(sym) => sym,
+        _ => unreachable!("symbol_of treat only ident object."),
     }
 }
 
-fn get_macro_code(sym: &Node, global_env: &GlobalEnv) -> Option<LinkedList<Inst>> {
-    if let Node::Ident(sym) = sym {
-        if let Some(stack_item) = global_env.get(sym) {
-            if let StackItem::Other(Node::Macro(code)) = stack_item {
-                Some(code.clone())
-            } else {
-                None
+/// 識別子を解決する。マクロ展開で導入された識別子 (`Node::Renamed`) が
+/// 展開先で束縛されていなければ、マクロを定義した環境で元の識別子を探す。
+fn resolve(
+    expr: &Node,
+    env: &Rc<RefCell<Env>>,
+    global_env: &GlobalEnv,
+) -> Result<Ref, CompileError> {
+    if let Some(binding) = env.borrow().lookup(expr) {
+        return Ok(match binding {
+            LocalBinding::Var(i, j) => Ref::Local(i, j),
+            LocalBinding::Syntax(syntax) => Ref::Syntax(syntax),
+        });
+    }
+    match expr {
+        Node::Ident(sym) => match global_env.get(*sym) {
+            Some(StackItem::Other(node @ (Node::Macro(_) | Node::Syntax(_)))) => {
+                Ok(Ref::Syntax(node.clone()))
+            }
+            _ => Ok(Ref::Global(*sym)),
+        },
+        Node::Renamed(base, _, def_env) => match resolve(base, &def_env.0, global_env)? {
+            Ref::Local(i, j) => match env_depth(env, &def_env.0) {
+                Some(depth) => Ok(Ref::Local(i + depth, j)),
+                None => Err(CompileError::MacroScope),
+            },
+            other => Ok(other),
+        },
+        _ => unreachable!("resolve treat only ident object."),
+    }
+}
+
+/// マクロの呼び出し `expr` を変換器 `transformer` で一段階展開する
+fn expand_macro(
+    transformer: &Node,
+    expr: &Node,
+    global_env: &mut GlobalEnv,
+) -> Result<Node, CompileError> {
+    match transformer {
+        Node::Syntax(rules) => rules.expand(expr).map_err(CompileError::SyntaxRules),
+        Node::Macro(lambda) => {
+            let args = expr.cdr().unwrap();
+            let given = args.list_parts().0.len();
+            if !lambda.arity.accepts(given) {
+                return Err(CompileError::WrongMacroArgCount {
+                    name: strip(&expr.car().unwrap()).inspect(),
+                    expected: lambda.arity,
+                    given,
+                });
+            }
+            let mut vm_ = VM::new(lambda.code.clone());
+
+            let mut macro_env = Env::new();
+            macro_env.set_args(args, lambda.arity);
+            vm_.set_env(Rc::new(RefCell::new(macro_env)));
+
+            let mut macro_dump = DumpStack::new();
+            macro_dump.push(DumpItem::new(
+                0,
+                Rc::new(RefCell::new(Env::new())),
+                Rc::from(vec![Inst::Stop]),
+                0,
+            ));
+            vm_.set_dump(macro_dump);
+
+            vm_.run(global_env)
+                .map_err(|err| CompileError::MacroExpansion(Box::new(err.error)))
+        }
+        _ => unreachable!("macro transformer must be Node::Syntax or Node::Macro."),
+    }
+}
+
+/// 先頭がマクロである限り、大域環境のもとで式を展開する。部分式は展開しない
+pub fn macroexpand(expr: Node, global_env: &mut GlobalEnv) -> Result<Node, CompileError> {
+    let env = Rc::new(RefCell::new(Env::new()));
+    let mut expr = expr;
+    loop {
+        let head = match expr.car() {
+            Some(fst) if is_identifier(&fst) => resolve(&fst, &env, global_env)?,
+            _ => return Ok(expr),
+        };
+        match head {
+            Ref::Syntax(transformer) => expr = expand_macro(&transformer, &expr, global_env)?,
+            _ => return Ok(expr),
+        }
+    }
+}
+
+/// `syntax-rules` の式をマクロの変換器に変換する。
+fn compile_syntax_rules(
+    spec: &Node,
+    env: &Rc<RefCell<Env>>,
+    def_env: Rc<RefCell<Env>>,
+    global_env: &GlobalEnv,
+) -> Result<Node, CompileError> {
+    if let Some(fst) = spec.car() {
+        if is_identifier(&fst) {
+            if let Ref::Global(ident) = resolve(&fst, env, global_env)? {
+                if ident == Symbol::SYNTAX_RULES {
+                    let rules =
+                        SyntaxRules::new(spec, def_env).map_err(CompileError::SyntaxRules)?;
+                    return Ok(Node::Syntax(Rc::new(rules)));
+                }
             }
-        } else {
-            None
         }
-    } else {
-        None
     }
+    Err(CompileError::BadTransformer)
 }
 
 #[cfg(test)]
 mod compiler_test {
-    use super::Compiler;
-    use crate::{ast::Node, env::init_global_env, inst::Inst, lexer::Lexer, parser::Parser};
-    use std::collections::LinkedList;
+    use super::{macroexpand, Compiler};
+    use crate::{
+        ast::{Arity, Node},
+        env::init_global_env,
+        error::CompileError,
+        inst::{Inst, Lambda},
+        lexer::Lexer,
+        parser::Parser,
+        span::Span,
+        symbol::Symbol,
+        vm::VM,
+    };
+    use std::rc::Rc;
+
+    fn read(source: &str) -> Node {
+        Parser::new(Lexer::new(source)).parse().unwrap().remove(0)
+    }
 
-    fn compile_test_template(test_name: &str, source: &str, expected: LinkedList<Inst>) {
+    /// `Inst::Ldf` を組み立てる
+    fn ldf(code: Vec<Inst>, arity: Arity, params: &str) -> Inst {
+        Inst::Ldf(Rc::new(Lambda {
+            code: code.into(),
+            arity,
+            params: read(params),
+            name: None,
+        }))
+    }
+
+    /// `define` で名前を付けられた `Inst::Ldf` にする
+    fn named(inst: Inst, name: &str) -> Inst {
+        match inst {
+            Inst::Ldf(lambda) => Inst::Ldf(Rc::new(Lambda {
+                code: lambda.code.clone(),
+                arity: lambda.arity,
+                params: lambda.params.clone(),
+                name: Some(Symbol::intern(name)),
+            })),
+            _ => unreachable!(),
+        }
+    }
+
+    fn compile_test_template(test_name: &str, source: &str, expected: Vec<Inst>) {
         let lex = Lexer::new(source);
         let mut p = Parser::new(lex);
         let mut nodes = p.parse().unwrap();
@@ -361,9 +598,7 @@ mod compiler_test {
     #[test]
     fn compile_integer_test() {
         let source = "1";
-        let mut expected = LinkedList::new();
-        expected.push_back(Inst::Ldc(Node::Int(1)));
-        expected.push_back(Inst::Stop);
+        let expected = vec![Inst::Ldc(Node::Int(1)), Inst::Stop];
 
         compile_test_template("compile_integer_test", source, expected);
     }
@@ -371,9 +606,7 @@ mod compiler_test {
     #[test]
     fn compile_quote_test() {
         let source = "(quote a)";
-        let mut expected = LinkedList::new();
-        expected.push_back(Inst::Ldc(Node::Ident("a".to_string())));
-        expected.push_back(Inst::Stop);
+        let expected = vec![Inst::Ldc(Node::ident("a")), Inst::Stop];
 
         compile_test_template("compile_quote_test", source, expected);
     }
@@ -381,32 +614,26 @@ mod compiler_test {
     #[test]
     fn compile_if_test() {
         let source0 = "(if #t 'a 'b)";
-
-        let mut expected0 = LinkedList::new();
-        expected0.push_back(Inst::Ldc(Node::Bool(true)));
-        let mut t_clause0 = LinkedList::new();
-        t_clause0.push_back(Inst::Ldc(Node::Ident("a".to_string())));
-        t_clause0.push_back(Inst::Join);
-        let mut f_clause0 = LinkedList::new();
-        f_clause0.push_back(Inst::Ldc(Node::Ident("b".to_string())));
-        f_clause0.push_back(Inst::Join);
-        expected0.push_back(Inst::Sel(t_clause0, f_clause0));
-        expected0.push_back(Inst::Stop);
+        let expected0 = vec![
+            Inst::Ldc(Node::Bool(true)),
+            Inst::Jf(4),
+            Inst::Ldc(Node::ident("a")),
+            Inst::Jmp(5),
+            Inst::Ldc(Node::ident("b")),
+            Inst::Stop,
+        ];
 
         compile_test_template("compile_if_test (source0)", source0, expected0);
 
         let source1 = "(if #f 'c)";
-
-        let mut expected1 = LinkedList::new();
-        expected1.push_back(Inst::Ldc(Node::Bool(false)));
-        let mut t_clause1 = LinkedList::new();
-        t_clause1.push_back(Inst::Ldc(Node::Ident("c".to_string())));
-        t_clause1.push_back(Inst::Join);
-        let mut f_clause1 = LinkedList::new();
-        f_clause1.push_back(Inst::Ldc(Node::Undef));
-        f_clause1.push_back(Inst::Join);
-        expected1.push_back(Inst::Sel(t_clause1, f_clause1));
-        expected1.push_back(Inst::Stop);
+        let expected1 = vec![
+            Inst::Ldc(Node::Bool(false)),
+            Inst::Jf(4),
+            Inst::Ldc(Node::ident("c")),
+            Inst::Jmp(5),
+            Inst::Ldc(Node::Undef),
+            Inst::Stop,
+        ];
 
         compile_test_template("compile_if_test (source1)", source1, expected1);
     }
@@ -414,44 +641,38 @@ mod compiler_test {
     #[test]
     fn compile_lambda_test() {
         let source0 = "(lambda (x) x)";
-        let mut expected0 = LinkedList::new();
-        let mut body_code = LinkedList::new();
-        body_code.push_back(Inst::Ld(0, 0));
-        body_code.push_back(Inst::Rtn);
-        expected0.push_back(Inst::Ldf(body_code));
-        expected0.push_back(Inst::Stop);
+        let body_code = vec![Inst::Ld(0, 0), Inst::Rtn];
+        let expected0 = vec![ldf(body_code, Arity::Exact(1), "(x)"), Inst::Stop];
 
         compile_test_template("compile_lambda_test (source0)", source0, expected0);
 
         let source1 = "(lambda () 1 2 3 4 5)";
-        let mut expected1 = LinkedList::new();
-        let mut body_code = LinkedList::new();
-        body_code.push_back(Inst::Ldc(Node::Int(1)));
-        body_code.push_back(Inst::Pop);
-        body_code.push_back(Inst::Ldc(Node::Int(2)));
-        body_code.push_back(Inst::Pop);
-        body_code.push_back(Inst::Ldc(Node::Int(3)));
-        body_code.push_back(Inst::Pop);
-        body_code.push_back(Inst::Ldc(Node::Int(4)));
-        body_code.push_back(Inst::Pop);
-        body_code.push_back(Inst::Ldc(Node::Int(5)));
-        body_code.push_back(Inst::Rtn);
-        expected1.push_back(Inst::Ldf(body_code));
-        expected1.push_back(Inst::Stop);
+        let body_code = vec![
+            Inst::Ldc(Node::Int(1)),
+            Inst::Pop,
+            Inst::Ldc(Node::Int(2)),
+            Inst::Pop,
+            Inst::Ldc(Node::Int(3)),
+            Inst::Pop,
+            Inst::Ldc(Node::Int(4)),
+            Inst::Pop,
+            Inst::Ldc(Node::Int(5)),
+            Inst::Rtn,
+        ];
+        let expected1 = vec![ldf(body_code, Arity::Exact(0), "()"), Inst::Stop];
 
         compile_test_template("compile_lambda_test (source1)", source1, expected1);
 
         let source2 = "(lambda (a . x) (cons a x))";
-        let mut expected2 = LinkedList::new();
-        let mut body_code = LinkedList::new();
-        body_code.push_back(Inst::Ld(0, 0));
-        body_code.push_back(Inst::Ld(0, -2));
-        body_code.push_back(Inst::Args(2));
-        body_code.push_back(Inst::Ldg(Node::Ident("cons".to_string())));
-        body_code.push_back(Inst::App);
-        body_code.push_back(Inst::Rtn);
-        expected2.push_back(Inst::Ldf(body_code));
-        expected2.push_back(Inst::Stop);
+        let body_code = vec![
+            Inst::Ld(0, 0),
+            Inst::Ld(0, -2),
+            Inst::Args(2),
+            Inst::Ldg(Symbol::intern("cons")),
+            Inst::TApp,
+            Inst::Rtn,
+        ];
+        let expected2 = vec![ldf(body_code, Arity::AtLeast(1), "(a . x)"), Inst::Stop];
 
         compile_test_template("compile_lambda_test (source2)", source2, expected2);
     }
@@ -459,48 +680,49 @@ mod compiler_test {
     #[test]
     fn compile_proc_call_test() {
         let source0 = "(car '(a b c))";
-        let mut expected0 = LinkedList::new();
-        expected0.push_back(Inst::Ldc(Node::List(vec![
-            Node::Ident("a".to_string()),
-            Node::Ident("b".to_string()),
-            Node::Ident("c".to_string()),
-            Node::nil(),
-        ])));
-        expected0.push_back(Inst::Args(1));
-        expected0.push_back(Inst::Ldg(Node::Ident("car".to_string())));
-        expected0.push_back(Inst::App);
-        expected0.push_back(Inst::Stop);
+        let expected0 = vec![
+            Inst::Ldc(Node::list(vec![
+                Node::ident("a"),
+                Node::ident("b"),
+                Node::ident("c"),
+            ])),
+            Inst::Args(1),
+            Inst::Ldg(Symbol::intern("car")),
+            Inst::App,
+            Inst::Stop,
+        ];
 
         compile_test_template("compile_proc_call_test (source0)", source0, expected0);
 
         let source1 = "((lambda (x) x) 'a)";
-        let mut expected1 = LinkedList::new();
-        expected1.push_back(Inst::Ldc(Node::Ident("a".to_string())));
-        expected1.push_back(Inst::Args(1));
-        let mut body_code = LinkedList::new();
-        body_code.push_back(Inst::Ld(0, 0));
-        body_code.push_back(Inst::Rtn);
-        expected1.push_back(Inst::Ldf(body_code));
-        expected1.push_back(Inst::App);
-        expected1.push_back(Inst::Stop);
+        let body_code = vec![Inst::Ld(0, 0), Inst::Rtn];
+        let expected1 = vec![
+            Inst::Ldc(Node::ident("a")),
+            Inst::Args(1),
+            ldf(body_code, Arity::Exact(1), "(x)"),
+            Inst::App,
+            Inst::Stop,
+        ];
 
         compile_test_template("compile_proc_call_test (source1)", source1, expected1);
 
         let source2 = "((lambda (x y) (cons x y)) 'a 'b)";
-        let mut expected2 = LinkedList::new();
-        expected2.push_back(Inst::Ldc(Node::Ident("a".to_string())));
-        expected2.push_back(Inst::Ldc(Node::Ident("b".to_string())));
-        expected2.push_back(Inst::Args(2));
-        let mut body_code = LinkedList::new();
-        body_code.push_back(Inst::Ld(0, 0));
-        body_code.push_back(Inst::Ld(0, 1));
-        body_code.push_back(Inst::Args(2));
-        body_code.push_back(Inst::Ldg(Node::Ident("cons".to_string())));
-        body_code.push_back(Inst::App);
-        body_code.push_back(Inst::Rtn);
-        expected2.push_back(Inst::Ldf(body_code));
-        expected2.push_back(Inst::App);
-        expected2.push_back(Inst::Stop);
+        let body_code = vec![
+            Inst::Ld(0, 0),
+            Inst::Ld(0, 1),
+            Inst::Args(2),
+            Inst::Ldg(Symbol::intern("cons")),
+            Inst::TApp,
+            Inst::Rtn,
+        ];
+        let expected2 = vec![
+            Inst::Ldc(Node::ident("a")),
+            Inst::Ldc(Node::ident("b")),
+            Inst::Args(2),
+            ldf(body_code, Arity::Exact(2), "(x y)"),
+            Inst::App,
+            Inst::Stop,
+        ];
 
         compile_test_template("compile_proc_call_test (source2)", source2, expected2);
     }
@@ -508,37 +730,189 @@ mod compiler_test {
     #[test]
     fn compile_define_test() {
         let source0 = "(define a 'b)";
-        let mut expected0 = LinkedList::new();
-        expected0.push_back(Inst::Ldc(Node::Ident("b".to_string())));
-        expected0.push_back(Inst::Def(Node::Ident("a".to_string())));
-        expected0.push_back(Inst::Stop);
+        let expected0 = vec![
+            Inst::Ldc(Node::ident("b")),
+            Inst::Def(Symbol::intern("a")),
+            Inst::Stop,
+        ];
 
         compile_test_template("compile_define_test (source0)", source0, expected0);
 
         let source1 = "(define list (lambda x x))";
-        let mut expected1 = LinkedList::new();
-        let mut body_code = LinkedList::new();
-        body_code.push_back(Inst::Ld(0, -1));
-        body_code.push_back(Inst::Rtn);
-        expected1.push_back(Inst::Ldf(body_code));
-        expected1.push_back(Inst::Def(Node::Ident("list".to_string())));
-        expected1.push_back(Inst::Stop);
+        let body_code = vec![Inst::Ld(0, -1), Inst::Rtn];
+        let expected1 = vec![
+            named(ldf(body_code, Arity::AtLeast(0), "x"), "list"),
+            Inst::Def(Symbol::intern("list")),
+            Inst::Stop,
+        ];
 
         compile_test_template("compile_define_test (source1)", source1, expected1);
 
         let source2 = "(define (times a b) (* a b))";
-        let mut expected2 = LinkedList::new();
-        let mut body_code = LinkedList::new();
-        body_code.push_back(Inst::Ld(0, 0));
-        body_code.push_back(Inst::Ld(0, 1));
-        body_code.push_back(Inst::Args(2));
-        body_code.push_back(Inst::Ldg(Node::Ident("*".to_string())));
-        body_code.push_back(Inst::App);
-        body_code.push_back(Inst::Rtn);
-        expected2.push_back(Inst::Ldf(body_code));
-        expected2.push_back(Inst::Def(Node::Ident("times".to_string())));
-        expected2.push_back(Inst::Stop);
+        let body_code = vec![
+            Inst::Ld(0, 0),
+            Inst::Ld(0, 1),
+            Inst::Args(2),
+            Inst::Ldg(Symbol::intern("*")),
+            Inst::TApp,
+            Inst::Rtn,
+        ];
+        let expected2 = vec![
+            named(ldf(body_code, Arity::Exact(2), "(a b)"), "times"),
+            Inst::Def(Symbol::intern("times")),
+            Inst::Stop,
+        ];
 
         compile_test_template("compile_define_test (source2)", source2, expected2);
     }
+
+    #[test]
+    fn compile_tail_call_test() {
+        // 末尾位置の if では各節が Rtn で終わるので、合流のための Jmp はない
+        let source = "(lambda (x) (if x (f x) 'a))";
+        let body_code = vec![
+            Inst::Ld(0, 0),
+            Inst::Jf(7),
+            Inst::Ld(0, 0),
+            Inst::Args(1),
+            Inst::Ldg(Symbol::intern("f")),
+            Inst::TApp,
+            Inst::Rtn,
+            Inst::Ldc(Node::ident("a")),
+            Inst::Rtn,
+        ];
+        let expected = vec![ldf(body_code, Arity::Exact(1), "(x)"), Inst::Stop];
+
+        compile_test_template("compile_tail_call_test", source, expected);
+    }
+
+    #[test]
+    fn compile_loc_test() {
+        let source = "(f\n (g) x)";
+        let mut p = Parser::new(Lexer::new(source));
+        let node = p.parse().unwrap().remove(0);
+        let spans = p.into_spans();
+        let mut global_env = init_global_env(None);
+        let result = Compiler::with_spans(node, &spans)
+            .compile(&mut global_env)
+            .unwrap();
+
+        let outer = Span::new(1, 1);
+        let inner = Span::new(2, 2);
+        let expected = vec![
+            Inst::Loc(outer),
+            Inst::Loc(inner),
+            Inst::Args(0),
+            Inst::Ldg(Symbol::intern("g")),
+            Inst::App,
+            // (g) の評価が終われば外側の式の位置に戻る
+            Inst::Loc(outer),
+            Inst::Ldg(Symbol::intern("x")),
+            Inst::Args(2),
+            Inst::Ldg(Symbol::intern("f")),
+            Inst::App,
+            Inst::Stop,
+        ];
+        assert_eq!(result, expected);
+
+        let source = "(lambda (x)\n  (if))";
+        let mut p = Parser::new(Lexer::new(source));
+        let node = p.parse().unwrap().remove(0);
+        let spans = p.into_spans();
+        let err = Compiler::with_spans(node, &spans)
+            .compile(&mut global_env)
+            .unwrap_err();
+        assert_eq!(err.span, Some(Span::new(2, 3)));
+    }
+
+    #[test]
+    fn compile_error_test() {
+        let cases = [
+            ("(if 1)", CompileError::ShortageOfArgs("if")),
+            (
+                "(let-syntax ())",
+                CompileError::ShortageOfArgs("let-syntax"),
+            ),
+            (
+                "(letrec-syntax ())",
+                CompileError::ShortageOfArgs("letrec-syntax"),
+            ),
+            ("(set! 1 2)", CompileError::NotIdentifier("set!")),
+            ("(define 1 2)", CompileError::BadDefineTarget),
+            ("(define ((f a) b) 1)", CompileError::BadDefineTarget),
+            ("(define (\"f\" a) 1)", CompileError::BadDefineTarget),
+            ("(1 . 2)", CompileError::ImproperList),
+            (
+                "(let 1)",
+                CompileError::SyntaxRules("no syntax rule matches the form."),
+            ),
+            (
+                "(lambda (x) let)",
+                CompileError::SyntaxAsVariable("let".to_string()),
+            ),
+        ];
+        for (source, expected) in cases {
+            let node = Parser::new(Lexer::new(source)).parse().unwrap().remove(0);
+            let mut global_env = init_global_env(None);
+            let err = Compiler::new(node).compile(&mut global_env).unwrap_err();
+            assert_eq!(err.error, expected, "source: {}", source);
+        }
+
+        // define-macro によるマクロの展開の失敗はコンパイルエラーになる
+        let mut global_env = init_global_env(None);
+        for source in [
+            "(define-macro bad (lambda () (car 1)))",
+            "(define-macro proc (lambda () car))",
+        ] {
+            let node = Parser::new(Lexer::new(source)).parse().unwrap().remove(0);
+            let code = Compiler::new(node).compile(&mut global_env).unwrap();
+            VM::new(code).run(&mut global_env).unwrap();
+        }
+        let node = Parser::new(Lexer::new("(bad)")).parse().unwrap().remove(0);
+        let err = Compiler::new(node).compile(&mut global_env).unwrap_err();
+        assert!(matches!(err.error, CompileError::MacroExpansion(_)));
+        let node = Parser::new(Lexer::new("(proc)")).parse().unwrap().remove(0);
+        let err = Compiler::new(node).compile(&mut global_env).unwrap_err();
+        assert_eq!(
+            err.error,
+            CompileError::InvalidExpr("#<primitive car>".to_string())
+        );
+
+        // 引数の個数が合わなければ展開しない
+        let node = Parser::new(Lexer::new("(bad 1)"))
+            .parse()
+            .unwrap()
+            .remove(0);
+        let err = Compiler::new(node).compile(&mut global_env).unwrap_err();
+        assert_eq!(
+            err.error,
+            CompileError::WrongMacroArgCount {
+                name: "bad".to_string(),
+                expected: Arity::Exact(0),
+                given: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn macroexpand_test() {
+        let mut global_env = init_global_env(None);
+        let source = "(define-syntax swap! (syntax-rules () ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+                      (define-macro my-if (lambda (c a b) (list 'cond (list c a) (list 'else b))))";
+        for node in Parser::new(Lexer::new(source)).parse().unwrap() {
+            let code = Compiler::new(node).compile(&mut global_env).unwrap();
+            VM::new(code).run(&mut global_env).unwrap();
+        }
+        let cases = [
+            ("(swap! x y)", "((lambda (tmp) (set! x y) (set! y tmp)) x)"),
+            ("(my-if t 1 2)", "(if t (begin 1) (cond (else 2)))"),
+            ("(car x)", "(car x)"),
+            ("42", "42"),
+        ];
+        for (source, expected) in cases {
+            let node = Parser::new(Lexer::new(source)).parse().unwrap().remove(0);
+            let expanded = macroexpand(node, &mut global_env).unwrap();
+            assert_eq!(expanded.inspect(), expected, "source: {}", source);
+        }
+    }
 }
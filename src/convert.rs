@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Arity, Node, Primitive},
+    error::{RuntimeError, TypeError},
+};
+
+/// Rust の値から Scheme の値への変換
+pub trait ToScheme {
+    fn to_scheme(self) -> Node;
+}
+
+/// Scheme の値から Rust の値への変換
+pub trait FromScheme: Sized {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError>;
+}
+
+impl ToScheme for Node {
+    fn to_scheme(self) -> Node {
+        self
+    }
+}
+
+impl FromScheme for Node {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+        Ok(value.clone())
+    }
+}
+
+impl ToScheme for () {
+    fn to_scheme(self) -> Node {
+        Node::Undef
+    }
+}
+
+impl ToScheme for i64 {
+    fn to_scheme(self) -> Node {
+        Node::Int(self)
+    }
+}
+
+impl FromScheme for i64 {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+        match value {
+            Node::Int(int) => Ok(*int),
+            _ => Err(TypeError::new("integer", value)),
+        }
+    }
+}
+
+impl ToScheme for bool {
+    fn to_scheme(self) -> Node {
+        Node::Bool(self)
+    }
+}
+
+impl FromScheme for bool {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+        match value {
+            Node::Bool(b) => Ok(*b),
+            _ => Err(TypeError::new("boolean", value)),
+        }
+    }
+}
+
+impl ToScheme for String {
+    fn to_scheme(self) -> Node {
+        Node::Str(self)
+    }
+}
+
+impl ToScheme for &str {
+    fn to_scheme(self) -> Node {
+        Node::Str(self.to_string())
+    }
+}
+
+impl FromScheme for String {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+        match value {
+            Node::Str(string) => Ok(string.clone()),
+            _ => Err(TypeError::new("string", value)),
+        }
+    }
+}
+
+impl ToScheme for char {
+    fn to_scheme(self) -> Node {
+        Node::Char(self)
+    }
+}
+
+impl FromScheme for char {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+        match value {
+            Node::Char(ch) => Ok(*ch),
+            _ => Err(TypeError::new("char", value)),
+        }
+    }
+}
+
+impl<T: ToScheme> ToScheme for Vec<T> {
+    fn to_scheme(self) -> Node {
+        Node::list(self.into_iter().map(ToScheme::to_scheme).collect())
+    }
+}
+
+impl<T: FromScheme> FromScheme for Vec<T> {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+        match value.to_vec() {
+            Some(items) => items.iter().map(T::from_scheme).collect(),
+            None => Err(TypeError::new("list", value)),
+        }
+    }
+}
+
+/// `None` は `#f` に対応する。
+/// `#f` と区別できないので、`Some(false)` のように `#f` になる値は `None` として読み戻される
+impl<T: ToScheme> ToScheme for Option<T> {
+    fn to_scheme(self) -> Node {
+        match self {
+            Some(value) => value.to_scheme(),
+            None => Node::Bool(false),
+        }
+    }
+}
+
+/// `#f` は常に `None` になる。`Option<bool>` では `Some(false)` を得られない
+impl<T: FromScheme> FromScheme for Option<T> {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+        match value {
+            Node::Bool(false) => Ok(None),
+            _ => T::from_scheme(value).map(Some),
+        }
+    }
+}
+
+/// キーを文字列とする連想リスト `(("key" . value) ...)` に対応する。
+/// 変換結果はキーの順に並ぶ
+impl<T: ToScheme> ToScheme for HashMap<String, T> {
+    fn to_scheme(self) -> Node {
+        let mut entries: Vec<(String, T)> = self.into_iter().collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Node::list(
+            entries
+                .into_iter()
+                .map(|(key, value)| Node::cons(Node::Str(key), value.to_scheme()))
+                .collect(),
+        )
+    }
+}
+
+/// キーには文字列のほかシンボルも使える
+impl<T: FromScheme> FromScheme for HashMap<String, T> {
+    fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+        let items = value
+            .to_vec()
+            .ok_or_else(|| TypeError::new("association list", value))?;
+        let mut map = HashMap::new();
+        for item in items {
+            let key = match item.car() {
+                Some(Node::Str(key)) => key,
+                Some(Node::Ident(key)) => key.to_string(),
+                _ => return Err(TypeError::new("pair with string key", &item)),
+            };
+            let value = T::from_scheme(&item.cdr().unwrap())?;
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+}
+
+// タプルは要素数の決まったリストに対応する
+macro_rules! impl_tuple {
+    ($len:expr; $($ty:ident $idx:tt),+) => {
+        impl<$($ty: ToScheme),+> ToScheme for ($($ty,)+) {
+            fn to_scheme(self) -> Node {
+                Node::list(vec![$(self.$idx.to_scheme()),+])
+            }
+        }
+
+        impl<$($ty: FromScheme),+> FromScheme for ($($ty,)+) {
+            fn from_scheme(value: &Node) -> Result<Self, TypeError> {
+                match value.to_vec() {
+                    Some(items) if items.len() == $len => {
+                        Ok(($($ty::from_scheme(&items[$idx])?,)+))
+                    }
+                    _ => Err(TypeError::new(
+                        concat!("list of ", stringify!($len), " elements"),
+                        value,
+                    )),
+                }
+            }
+        }
+    };
+}
+
+impl_tuple!(1; A 0);
+impl_tuple!(2; A 0, B 1);
+impl_tuple!(3; A 0, B 1, C 2);
+impl_tuple!(4; A 0, B 1, C 2, D 3);
+
+/// 型付きの関数の戻り値。失敗しうる関数は `Result` を返せる
+pub trait IntoResult {
+    fn into_result(self) -> Result<Node, RuntimeError>;
+}
+
+impl<T: ToScheme> IntoResult for T {
+    fn into_result(self) -> Result<Node, RuntimeError> {
+        Ok(self.to_scheme())
+    }
+}
+
+impl<T: ToScheme> IntoResult for Result<T, RuntimeError> {
+    fn into_result(self) -> Result<Node, RuntimeError> {
+        self.map(ToScheme::to_scheme)
+    }
+}
+
+/// 引数と戻り値を自動で変換して、Rust の関数を組み込み手続きにする。
+/// `Args` は実装を区別するための引数の型の組
+pub trait TypedFn<Args> {
+    fn into_primitive(self, name: &str) -> Primitive;
+}
+
+/// `position` 番目（1 始まり）の引数を変換する
+pub fn convert_arg<T: FromScheme>(
+    name: &str,
+    position: usize,
+    arg: &Node,
+) -> Result<T, RuntimeError> {
+    T::from_scheme(arg).map_err(|error| RuntimeError::WrongType {
+        name: name.to_string(),
+        position,
+        error,
+    })
+}
+
+macro_rules! impl_typed_fn {
+    ($len:expr; $($ty:ident $idx:tt),*) => {
+        impl<Func, Ret, $($ty),*> TypedFn<($($ty,)*)> for Func
+        where
+            Func: Fn($($ty),*) -> Ret + 'static,
+            Ret: IntoResult,
+            $($ty: FromScheme,)*
+        {
+            #[allow(unused_variables)]
+            fn into_primitive(self, name: &str) -> Primitive {
+                let prim_name = name.to_string();
+                Primitive::new(name, Arity::Exact($len), move |args| {
+                    self($(convert_arg::<$ty>(&prim_name, $idx + 1, &args[$idx])?),*).into_result()
+                })
+            }
+        }
+    };
+}
+
+impl_typed_fn!(0;);
+impl_typed_fn!(1; A 0);
+impl_typed_fn!(2; A 0, B 1);
+impl_typed_fn!(3; A 0, B 1, C 2);
+impl_typed_fn!(4; A 0, B 1, C 2, D 3);
+impl_typed_fn!(5; A 0, B 1, C 2, D 3, E 4);
+impl_typed_fn!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+
+#[cfg(test)]
+mod convert_test {
+    use std::collections::HashMap;
+
+    use super::{FromScheme, ToScheme};
+    use crate::{ast::Node, error::TypeError, lexer::Lexer, parser::Parser};
+
+    fn read(src: &str) -> Node {
+        Parser::new(Lexer::new(src)).parse().unwrap().remove(0)
+    }
+
+    #[test]
+    fn to_scheme_test() {
+        assert_eq!(42i64.to_scheme(), Node::Int(42));
+        assert_eq!("abc".to_scheme(), Node::Str("abc".to_string()));
+        assert_eq!(vec![1i64, 2, 3].to_scheme().inspect(), "(1 2 3)");
+        assert_eq!(Vec::<i64>::new().to_scheme(), Node::Nil);
+        assert_eq!(None::<i64>.to_scheme(), Node::Bool(false));
+        assert_eq!((1i64, "a", true).to_scheme().inspect(), "(1 \"a\" #t)");
+
+        let mut map = HashMap::new();
+        map.insert("b".to_string(), vec![2i64]);
+        map.insert("a".to_string(), vec![]);
+        assert_eq!(map.to_scheme().inspect(), "((\"a\") (\"b\" 2))");
+    }
+
+    #[test]
+    fn from_scheme_test() {
+        assert_eq!(i64::from_scheme(&read("-7")), Ok(-7));
+        assert_eq!(String::from_scheme(&read("\"hi\"")), Ok("hi".to_string()));
+        assert_eq!(
+            Vec::<(String, bool)>::from_scheme(&read("((\"x\" #t) (\"y\" #f))")),
+            Ok(vec![("x".to_string(), true), ("y".to_string(), false)])
+        );
+        assert_eq!(Option::<i64>::from_scheme(&read("#f")), Ok(None));
+        assert_eq!(Option::<i64>::from_scheme(&read("3")), Ok(Some(3)));
+
+        let map = HashMap::<String, i64>::from_scheme(&read("((a . 1) (\"b\" . 2))")).unwrap();
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map.get("b"), Some(&2));
+
+        // 往復しても値が変わらない
+        let list = vec![Some(vec![1i64]), None];
+        assert_eq!(
+            Vec::<Option<Vec<i64>>>::from_scheme(&list.clone().to_scheme()),
+            Ok(list)
+        );
+
+        // `Some(false)` は `#f` と区別できず、`None` として読み戻される
+        assert_eq!(Some(false).to_scheme(), Node::Bool(false));
+        assert_eq!(
+            Option::<bool>::from_scheme(&Some(false).to_scheme()),
+            Ok(None)
+        );
+        assert_eq!(Option::<bool>::from_scheme(&read("#t")), Ok(Some(true)));
+    }
+
+    #[test]
+    fn type_error_test() {
+        assert_eq!(
+            i64::from_scheme(&read("\"1\"")),
+            Err(TypeError {
+                expected: "integer".to_string(),
+                given: "\"1\"".to_string(),
+            })
+        );
+        assert_eq!(
+            Vec::<i64>::from_scheme(&read("(1 . 2)"))
+                .unwrap_err()
+                .expected,
+            "list"
+        );
+        assert_eq!(
+            Vec::<i64>::from_scheme(&read("(1 #t)")).unwrap_err().given,
+            "#t"
+        );
+        assert_eq!(
+            <(i64, i64)>::from_scheme(&read("(1 2 3)"))
+                .unwrap_err()
+                .expected,
+            "list of 2 elements"
+        );
+    }
+}
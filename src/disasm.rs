@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::{ast::Node, inst::Inst};
+
+/// コンパイル済みのコードを読みやすい形式の文字列にする。
+/// 手続きの本体はラベルを付けて字下げし、飛び先にもラベルを付ける。
+/// 局所変数には仮引数の名前を添える
+pub fn disassemble(code: &[Inst]) -> String {
+    let mut dis = Disassembler {
+        out: String::new(),
+        labels: 0,
+        frames: Vec::new(),
+    };
+    dis.block(code, 0);
+    dis.out
+}
+
+struct Disassembler {
+    out: String,
+    // これまでに付けたラベルの数
+    labels: usize,
+    // 外側から順に並べた、各フレームの仮引数リスト
+    frames: Vec<Node>,
+}
+
+impl Disassembler {
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn op(&mut self, depth: usize, name: &str, operand: &str, comment: Option<String>) {
+        let line = format!("{}{:<8}{}", "    ".repeat(depth), name, operand);
+        match comment {
+            Some(comment) => writeln!(self.out, "{:<32}; {}", line.trim_end(), comment),
+            None => writeln!(self.out, "{}", line.trim_end()),
+        }
+        .unwrap();
+    }
+
+    fn label(&mut self, depth: usize, label: &str) {
+        writeln!(self.out, "{}  {}:", "    ".repeat(depth), label).unwrap();
+    }
+
+    /// `(i, j)` で参照される局所変数の名前
+    fn local_name(&self, i: usize, j: isize) -> Option<String> {
+        let params = self.frames.iter().rev().nth(i)?;
+        let (required, rest) = params.list_parts();
+        let name = if 0 <= j {
+            required.get(j as usize)?.clone()
+        } else if (-(j + 1)) as usize == required.len() && !rest.is_null() {
+            rest
+        } else {
+            return None;
+        };
+        Some(name.inspect())
+    }
+
+    /// 飛び先の位置に付けたラベル。飛び先は常に前方にあるので、出会った順に番号を振る
+    fn jump_label(&mut self, targets: &mut HashMap<usize, String>, target: usize) -> String {
+        if let Some(label) = targets.get(&target) {
+            return label.clone();
+        }
+        let label = self.new_label();
+        targets.insert(target, label.clone());
+        label
+    }
+
+    fn block(&mut self, code: &[Inst], depth: usize) {
+        let mut targets = HashMap::new();
+        for (pc, inst) in code.iter().enumerate() {
+            if let Some(label) = targets.get(&pc) {
+                // 飛び先のラベルは命令より少し左に出す
+                let indent = (depth * 4).saturating_sub(2);
+                writeln!(self.out, "{:indent$}{}:", "", label, indent = indent).unwrap();
+            }
+            match inst {
+                Inst::Ld(i, j) | Inst::Lset(i, j) => {
+                    let name = if matches!(inst, Inst::Ld(_, _)) {
+                        "ld"
+                    } else {
+                        "lset"
+                    };
+                    let comment = self.local_name(*i, *j);
+                    self.op(depth, name, &format!("{} {}", i, j), comment);
+                }
+                Inst::Ldc(node) => self.op(depth, "ldc", &node.inspect(), None),
+                Inst::Ldg(sym) => self.op(depth, "ldg", &sym.name(), None),
+                Inst::Gset(sym) => self.op(depth, "gset", &sym.name(), None),
+                Inst::Def(sym) => self.op(depth, "def", &sym.name(), None),
+                Inst::Defm(sym) => self.op(depth, "defm", &sym.name(), None),
+                Inst::Args(num) => self.op(depth, "args", &num.to_string(), None),
+                Inst::Ldf(lambda) => {
+                    let label = self.new_label();
+                    let comment = Some(format!("arity {}", lambda.arity));
+                    self.op(
+                        depth,
+                        "ldf",
+                        &format!("{} {}", label, lambda.params.inspect()),
+                        comment,
+                    );
+                    self.label(depth, &label);
+                    self.frames.push(lambda.params.clone());
+                    self.block(&lambda.code, depth + 1);
+                    self.frames.pop();
+                }
+                Inst::Jf(target) | Inst::Jmp(target) => {
+                    let name = if matches!(inst, Inst::Jf(_)) {
+                        "jf"
+                    } else {
+                        "jmp"
+                    };
+                    let label = self.jump_label(&mut targets, *target);
+                    self.op(depth, name, &label, None);
+                }
+                Inst::App => self.op(depth, "app", "", None),
+                Inst::TApp => self.op(depth, "tapp", "", None),
+                Inst::Rtn => self.op(depth, "rtn", "", None),
+                Inst::Pop => self.op(depth, "pop", "", None),
+                Inst::Stop => self.op(depth, "stop", "", None),
+                // 連続する loc は、間に飛び先がなければ最後のものだけが意味を持つ
+                Inst::Loc(_)
+                    if matches!(code.get(pc + 1), Some(Inst::Loc(_)))
+                        && !targets.contains_key(&(pc + 1)) => {}
+                Inst::Loc(span) => self.op(depth, "loc", &span.to_string(), None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod disasm_test {
+    use super::disassemble;
+    use crate::{compiler::Compiler, env::init_global_env, lexer::Lexer, parser::Parser};
+
+    fn disasm_source(source: &str) -> String {
+        let mut global_env = init_global_env(None);
+        let node = Parser::new(Lexer::new(source)).parse().unwrap().remove(0);
+        let code = Compiler::new(node).compile(&mut global_env).unwrap();
+        disassemble(&code)
+    }
+
+    #[test]
+    fn disassemble_test() {
+        let source = "(define (f x . rest) (if (null? rest) '(1 \"a\") (lambda (y) (set! x y))))";
+        let expected = "\
+ldf     L1 (x . rest)           ; arity at least 1
+  L1:
+    ld      0 -2                ; rest
+    args    1
+    ldg     null?
+    app
+    jf      L2
+    ldc     (1 \"a\")
+    rtn
+  L2:
+    ldf     L3 (y)              ; arity 1
+      L3:
+        ld      0 0             ; y
+        lset    1 0             ; x
+        rtn
+    rtn
+def     f
+stop
+";
+        assert_eq!(disasm_source(source), expected);
+
+        // 末尾位置にない if は節の後で合流する
+        let expected = "\
+ldg     x
+jf      L1
+ldc     1
+jmp     L2
+L1:
+ldc     2
+L2:
+args    1
+ldg     display
+app
+stop
+";
+        assert_eq!(disasm_source("(display (if x 1 2))"), expected);
+    }
+
+    #[test]
+    fn disassemble_loc_test() {
+        let mut global_env = init_global_env(None);
+        let mut p = Parser::new(Lexer::new("(f\n (g x))"));
+        let node = p.parse().unwrap().remove(0);
+        let spans = p.into_spans();
+        let code = Compiler::with_spans(node, &spans)
+            .compile(&mut global_env)
+            .unwrap();
+        // 連続する loc はまとめる
+        let expected = "\
+loc     2:2
+ldg     x
+args    1
+ldg     g
+app
+loc     1:1
+args    1
+ldg     f
+app
+stop
+";
+        assert_eq!(disassemble(&code), expected);
+    }
+}
@@ -1,51 +1,67 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::rc::Rc;
 
 use crate::{
-    ast::{Node, ProcTag},
+    ast::{Arity, Control, Node, Primitive, ProcTag},
     compiler::Compiler,
     lexer::Lexer,
     parser::Parser,
     primitive::*,
+    span::Source,
+    symbol::Symbol,
     vm::{StackItem, VM},
 };
 
 fn position_var(sym: &Node, list: &Node) -> Option<isize> {
-    match list {
-        Node::List(list) => {
-            for (i, node) in list.iter().enumerate() {
-                if node == sym {
-                    if i == list.len() - 1 {
-                        return Some(-((i as isize) + 1));
-                    } else {
-                        return Some(i as isize);
-                    }
-                }
-            }
-            None
-        }
-        Node::Ident(_) => {
-            if sym == list {
-                Some(-1)
-            } else {
-                None
-            }
-        }
-        _ => None,
+    let (params, rest) = list.list_parts();
+    if let Some(i) = params.iter().position(|node| node == sym) {
+        Some(i as isize)
+    } else if rest == *sym {
+        Some(-(params.len() as isize) - 1)
+    } else {
+        None
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// `position_var` で求めた位置に対応する、フレーム中の変数の添字。
+/// 残余引数は必須引数の直後に置かれる
+fn slot_index(j: isize) -> usize {
+    if 0 <= j {
+        j as usize
+    } else {
+        (-(j + 1)) as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Env {
+    // コンパイル時に使う仮引数リスト
     node: Node,
+    // 実行時の変数の値
+    slots: Vec<Node>,
+    syntax: Vec<(Node, Node)>,
     next_env: Option<Rc<RefCell<Env>>>,
 }
 
+/// コンパイル時に局所環境から見つかった束縛
+#[derive(Debug, Clone, PartialEq)]
+pub enum LocalBinding {
+    Var(usize, isize),
+    Syntax(Node),
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Env {
     pub fn new() -> Self {
         Env {
             node: Node::nil(),
+            slots: Vec::new(),
+            syntax: Vec::new(),
             next_env: None,
         }
     }
@@ -66,31 +82,30 @@ impl Env {
         self.location_helper(expr, 0)
     }
 
+    fn lookup_helper(&self, expr: &Node, i: usize) -> Option<LocalBinding> {
+        if let Some(j) = position_var(expr, &self.node) {
+            Some(LocalBinding::Var(i, j))
+        } else if let Some((_, syntax)) = self.syntax.iter().find(|(name, _)| name == expr) {
+            Some(LocalBinding::Syntax(syntax.clone()))
+        } else if let Some(next_env) = &self.next_env {
+            next_env.borrow().lookup_helper(expr, i + 1)
+        } else {
+            None
+        }
+    }
+
+    /// 変数だけでなく、`let-syntax` などで束縛された局所マクロも探す
+    pub fn lookup(&self, expr: &Node) -> Option<LocalBinding> {
+        self.lookup_helper(expr, 0)
+    }
+
     fn get_helper(&self, tmp_i: usize, target_i: usize, j: isize) -> Option<Node> {
         if tmp_i == target_i {
-            if let Node::List(nodes) = &self.node {
-                let mut nodes = nodes.clone();
-                if 0 <= j {
-                    nodes.get(j as usize).map(|node| node.clone())
-                } else {
-                    for _ in 0..(-(j + 1)) {
-                        nodes.remove(0);
-                    }
-                    Some(Node::List(nodes))
-                }
-            } else {
-                if j == -1 {
-                    Some(self.node.clone())
-                } else {
-                    None
-                }
-            }
+            self.slots.get(slot_index(j)).cloned()
+        } else if let Some(next_env) = &self.next_env {
+            next_env.borrow().get_helper(tmp_i + 1, target_i, j)
         } else {
-            if let Some(next_env) = &self.next_env {
-                next_env.borrow().get_helper(tmp_i + 1, target_i, j)
-            } else {
-                None
-            }
+            None
         }
     }
 
@@ -100,41 +115,12 @@ impl Env {
 
     fn set_helper(&mut self, tmp_i: usize, target_i: usize, j: isize, val: Node) -> Option<()> {
         if tmp_i == target_i {
-            if 0 <= j {
-                if let Node::List(mut nodes) = self.node.clone() {
-                    nodes[j as usize] = val;
-                    self.node = Node::List(nodes);
-                    Some(())
-                } else {
-                    unreachable!();
-                }
-            } else {
-                if j == -1 {
-                    self.node = val;
-                    Some(())
-                } else {
-                    if let Node::List(mut nodes) = self.node.clone() {
-                        nodes.truncate((-(j + 1)) as usize);
-                        if let Node::List(val_nodes) = val {
-                            for val_node in val_nodes {
-                                nodes.push(val_node);
-                            }
-                        } else {
-                            nodes.push(val);
-                        }
-                        self.node = Node::List(nodes);
-                        Some(())
-                    } else {
-                        unreachable!();
-                    }
-                }
-            }
+            *self.slots.get_mut(slot_index(j))? = val;
+            Some(())
+        } else if let Some(next) = &self.next_env {
+            next.borrow_mut().set_helper(tmp_i + 1, target_i, j, val)
         } else {
-            if let Some(next) = &self.next_env {
-                next.borrow_mut().set_helper(tmp_i + 1, target_i, j, val)
-            } else {
-                None
-            }
+            None
         }
     }
 
@@ -146,19 +132,93 @@ impl Env {
         self.next_env = Some(env);
     }
 
-    pub fn set_node(&mut self, lvar: Node) {
-        self.node = lvar;
+    pub fn set_node(&mut self, params: Node) {
+        self.node = params;
+    }
+
+    /// 実引数のリストから変数の値を並べる。残余引数はここでリストにまとめておく
+    pub fn set_args(&mut self, args: Node, arity: Arity) {
+        let (mut slots, _) = args.list_parts();
+        if let Arity::AtLeast(required) = arity {
+            if required <= slots.len() {
+                let rest = slots.split_off(required);
+                slots.push(Node::list(rest));
+            }
+        }
+        self.slots = slots;
+    }
+
+    pub fn add_syntax(&mut self, name: Node, syntax: Node) {
+        self.syntax.push((name, syntax));
+    }
+
+    /// 実行時の変数の値
+    pub fn slots(&self) -> &[Node] {
+        &self.slots
+    }
+
+    pub fn next_env(&self) -> Option<&Rc<RefCell<Env>>> {
+        self.next_env.as_ref()
+    }
+
+    /// 変数の値と親のフレームへの参照を手放す
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.next_env = None;
     }
 }
 
-pub type GlobalEnv = HashMap<String, StackItem>;
+/// `env` から親をたどって `target` に至るまでのフレーム数を返す
+pub fn env_depth(env: &Rc<RefCell<Env>>, target: &Rc<RefCell<Env>>) -> Option<usize> {
+    if Rc::ptr_eq(env, target) {
+        Some(0)
+    } else if let Some(next_env) = &env.borrow().next_env {
+        env_depth(next_env, target).map(|depth| depth + 1)
+    } else {
+        None
+    }
+}
+
+/// 大域変数の表。シンボルの番号をそのまま添字として使うので、
+/// コンパイル時に解決したシンボルから直接値を引ける
+#[derive(Debug, Clone, Default)]
+pub struct GlobalEnv {
+    slots: Vec<Option<StackItem>>,
+}
+
+impl GlobalEnv {
+    pub fn new() -> Self {
+        GlobalEnv { slots: Vec::new() }
+    }
+
+    pub fn get(&self, sym: Symbol) -> Option<&StackItem> {
+        self.slots.get(sym.id())?.as_ref()
+    }
+
+    /// 値を定義し、以前の値があれば返す
+    pub fn insert(&mut self, sym: Symbol, item: StackItem) -> Option<StackItem> {
+        if self.slots.len() <= sym.id() {
+            self.slots.resize(sym.id() + 1, None);
+        }
+        self.slots[sym.id()].replace(item)
+    }
+
+    /// 定義されている変数の名前
+    pub fn names(&self) -> impl Iterator<Item = Symbol> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.is_some())
+            .map(|(id, _)| Symbol::from_id(id))
+    }
+}
 
 macro_rules! register_primitive {
-    ($env:expr, $name:expr, $func:expr) => {
+    ($env:expr, $name:expr, $func:expr, $arity:expr) => {
         $env.insert(
-            $name.to_string(),
+            Symbol::intern($name),
             StackItem::new(
-                Node::Primitive($name.to_string(), $func),
+                Node::Primitive(Rc::new(Primitive::new($name, $arity, $func))),
                 Some(ProcTag::Primitive),
             ),
         );
@@ -168,43 +228,157 @@ macro_rules! register_primitive {
 pub fn init_global_env(sources: Option<Vec<String>>) -> GlobalEnv {
     let mut env = GlobalEnv::new();
 
-    register_primitive!(env, "car", prim_car);
-    register_primitive!(env, "cdr", prim_cdr);
-    register_primitive!(env, "cons", prim_cons);
-    register_primitive!(env, "eq?", prim_eq);
-    register_primitive!(env, "eqv?", prim_eqv);
-    register_primitive!(env, "pair?", prim_pair);
-    register_primitive!(env, "display", prim_display);
-    register_primitive!(env, "newline", prim_newline);
-    register_primitive!(env, "+", prim_plus);
-    register_primitive!(env, "*", prim_times);
-    register_primitive!(env, "-", prim_minus);
-    register_primitive!(env, "div", prim_div);
-    register_primitive!(env, "modulo", prim_modulo);
-    register_primitive!(env, "=", prim_ope_equal);
-    register_primitive!(env, "<", prim_lt);
-    register_primitive!(env, ">", prim_gt);
-    register_primitive!(env, "<=", prim_le);
-    register_primitive!(env, ">=", prim_ge);
-
-    compile_lib(&mut env, include_str!("mlib.scm"));
+    register_primitive!(env, "car", prim_car, Arity::Exact(1));
+    register_primitive!(env, "cdr", prim_cdr, Arity::Exact(1));
+    register_primitive!(env, "cons", prim_cons, Arity::Exact(2));
+    register_primitive!(env, "set-car!", prim_set_car, Arity::Exact(2));
+    register_primitive!(env, "set-cdr!", prim_set_cdr, Arity::Exact(2));
+    register_primitive!(env, "eq?", prim_eq, Arity::Exact(2));
+    register_primitive!(env, "eqv?", prim_eqv, Arity::Exact(2));
+    register_primitive!(env, "pair?", prim_pair, Arity::Exact(1));
+    register_primitive!(env, "display", prim_display, Arity::Exact(1));
+    register_primitive!(env, "write", prim_write, Arity::Exact(1));
+    register_primitive!(env, "newline", prim_newline, Arity::Exact(0));
+    register_primitive!(env, "+", prim_plus, Arity::AtLeast(0));
+    register_primitive!(env, "*", prim_times, Arity::AtLeast(0));
+    register_primitive!(env, "-", prim_minus, Arity::AtLeast(1));
+    register_primitive!(env, "div", prim_div, Arity::Exact(2));
+    register_primitive!(env, "modulo", prim_modulo, Arity::Exact(2));
+    register_primitive!(env, "=", prim_ope_equal, Arity::AtLeast(2));
+    register_primitive!(env, "<", prim_lt, Arity::AtLeast(2));
+    register_primitive!(env, ">", prim_gt, Arity::AtLeast(2));
+    register_primitive!(env, "<=", prim_le, Arity::AtLeast(2));
+    register_primitive!(env, ">=", prim_ge, Arity::AtLeast(2));
+    register_primitive!(env, "/", prim_slash, Arity::AtLeast(1));
+    register_primitive!(env, "number?", prim_number, Arity::Exact(1));
+    register_primitive!(
+        env,
+        "exact->inexact",
+        prim_exact_to_inexact,
+        Arity::Exact(1)
+    );
+    register_primitive!(env, "inexact", prim_inexact, Arity::Exact(1));
+    register_primitive!(env, "exact", prim_inexact_to_exact, Arity::Exact(1));
+    register_primitive!(env, "floor", prim_floor, Arity::Exact(1));
+    register_primitive!(env, "round", prim_round, Arity::Exact(1));
+    register_primitive!(env, "sqrt", prim_sqrt, Arity::Exact(1));
+    register_primitive!(env, "expt", prim_expt, Arity::Exact(2));
+    register_primitive!(
+        env,
+        "number->string",
+        prim_number_to_string,
+        Arity::Range(1, 2)
+    );
+    register_primitive!(
+        env,
+        "string->number",
+        prim_string_to_number,
+        Arity::Range(1, 2)
+    );
+    register_primitive!(env, "symbol?", prim_symbol, Arity::Exact(1));
+    register_primitive!(
+        env,
+        "string->symbol",
+        prim_string_to_symbol,
+        Arity::Exact(1)
+    );
+    register_primitive!(
+        env,
+        "symbol->string",
+        prim_symbol_to_string,
+        Arity::Exact(1)
+    );
+    register_primitive!(env, "gensym", prim_gensym, Arity::Range(0, 1));
+    register_primitive!(env, "char?", prim_char, Arity::Exact(1));
+    register_primitive!(env, "char->integer", prim_char_to_integer, Arity::Exact(1));
+    register_primitive!(env, "integer->char", prim_integer_to_char, Arity::Exact(1));
+    register_primitive!(env, "char=?", prim_char_equal, Arity::AtLeast(2));
+    register_primitive!(env, "char<?", prim_char_lt, Arity::AtLeast(2));
+    register_primitive!(env, "char-upcase", prim_char_upcase, Arity::Exact(1));
+    register_primitive!(env, "char-downcase", prim_char_downcase, Arity::Exact(1));
+    register_primitive!(
+        env,
+        "char-alphabetic?",
+        prim_char_alphabetic,
+        Arity::Exact(1)
+    );
+    register_primitive!(env, "char-numeric?", prim_char_numeric, Arity::Exact(1));
+    register_primitive!(
+        env,
+        "char-whitespace?",
+        prim_char_whitespace,
+        Arity::Exact(1)
+    );
+    register_primitive!(env, "string?", prim_string, Arity::Exact(1));
+    register_primitive!(env, "string-length", prim_string_length, Arity::Exact(1));
+    register_primitive!(env, "string-ref", prim_string_ref, Arity::Exact(2));
+    register_primitive!(env, "substring", prim_substring, Arity::Range(2, 3));
+    register_primitive!(env, "string-append", prim_string_append, Arity::AtLeast(0));
+    register_primitive!(env, "string=?", prim_string_equal, Arity::AtLeast(2));
+    register_primitive!(env, "string<?", prim_string_lt, Arity::AtLeast(2));
+    register_primitive!(env, "string->list", prim_string_to_list, Arity::Exact(1));
+    register_primitive!(env, "list->string", prim_list_to_string, Arity::Exact(1));
+    register_primitive!(env, "string-upcase", prim_string_upcase, Arity::Exact(1));
+    register_primitive!(
+        env,
+        "string-downcase",
+        prim_string_downcase,
+        Arity::Exact(1)
+    );
+    register_primitive!(env, "string-index", prim_string_index, Arity::Exact(2));
+    register_primitive!(
+        env,
+        "string-contains",
+        prim_string_contains,
+        Arity::Exact(2)
+    );
+    register_primitive!(env, "string-split", prim_string_split, Arity::Range(1, 2));
+    register_primitive!(env, "string-join", prim_string_join, Arity::Range(1, 2));
+    register_primitive!(env, "gc", prim_gc, Arity::Exact(0));
+    register_primitive!(env, "heap-stats", prim_heap_stats, Arity::Exact(0));
+    register_primitive!(env, "exit", prim_exit, Arity::Range(0, 1));
+    // call/cc は VM が直接処理する
+    let callcc = StackItem::new(
+        Node::Primitive(Rc::new(Primitive::control(
+            "call/cc",
+            Arity::Exact(1),
+            Control::CallCc,
+        ))),
+        Some(ProcTag::Primitive),
+    );
+    env.insert(Symbol::intern("call/cc"), callcc.clone());
+    env.insert(Symbol::intern("call-with-current-continuation"), callcc);
+
+    if let Err(msg) = compile_lib(&mut env, Source::new("mlib.scm", include_str!("mlib.scm"))) {
+        unreachable!("failed to load the builtin library: {}", msg);
+    }
 
     if let Some(sources) = sources {
         for source in sources {
-            compile_lib(&mut env, source);
+            // 読み込みに失敗したライブラリは報告して読み飛ばす
+            if let Err(msg) = compile_lib(&mut env, Source::new("<load>", source)) {
+                eprintln!("load error: {}", msg);
+            }
         }
     }
 
     env
 }
 
-fn compile_lib<T: AsRef<str>>(global_env: &mut GlobalEnv, lib_source: T) {
-    let lex = Lexer::new(lib_source.as_ref());
+fn compile_lib(global_env: &mut GlobalEnv, source: Source) -> Result<(), String> {
+    let lex = Lexer::new(source.text());
     let mut p = Parser::new(lex);
-    let nodes = p.parse().unwrap();
+    let nodes = p
+        .parse()
+        .map_err(|err| source.describe(err.error, err.span))?;
     for node in nodes {
         let comp = Compiler::new(node);
-        let code = comp.compile(global_env).unwrap();
-        VM::new(code).run(global_env);
+        let code = comp
+            .compile(global_env)
+            .map_err(|err| source.describe(err.error, err.span))?;
+        VM::new(code)
+            .run(global_env)
+            .map_err(|err| source.describe(err.error, err.span))?;
     }
+    Ok(())
 }
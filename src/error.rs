@@ -0,0 +1,356 @@
+use std::error;
+use std::fmt;
+
+use crate::{
+    ast::{Arity, Node},
+    span::{Located, Source, Span},
+};
+
+/// 字句解析のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    /// 閉じられていない文字列リテラル
+    UnterminatedString,
+    /// 数字で始まるが数値として解釈できない字句
+    InvalidNumber(String),
+    /// `#` に続く解釈できない字句
+    InvalidHashSyntax(String),
+    /// 識別子などの途中に現れた開き括弧
+    UnexpectedParen(String),
+    /// 入力の末尾にある `,`
+    DanglingComma,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexError::UnterminatedString => write!(f, "string literal is not terminated."),
+            LexError::InvalidNumber(token) => write!(f, "invalid number literal: {}", token),
+            LexError::InvalidHashSyntax(token) => write!(f, "invalid `#` syntax: {}", token),
+            LexError::UnexpectedParen(token) => {
+                write!(f, "unexpected open paren after `{}`.", token)
+            }
+            LexError::DanglingComma => write!(f, "missing expr next to the unquote."),
+        }
+    }
+}
+
+impl error::Error for LexError {}
+
+/// 構文解析のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Lex(LexError),
+    /// 式の始まりとして不正なトークン（表記）
+    UnexpectedToken(String),
+    /// 閉じられていないリスト
+    Unclosed,
+    /// quote などの省略記法の後に式がない
+    MissingDatum(&'static str),
+    /// quasiquote の後にリストがない
+    QuasiquoteNotList,
+    /// `.` の位置が不正なリスト
+    BadDottedList,
+}
+
+impl ParseError {
+    /// 入力が途中で終わったために起きたエラーか。
+    /// 続きを読めば解析できる可能性がある
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            ParseError::Lex(LexError::UnterminatedString | LexError::DanglingComma)
+                | ParseError::Unclosed
+                | ParseError::MissingDatum(_)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Lex(err) => err.fmt(f),
+            ParseError::UnexpectedToken(token) => write!(f, "unexpected `{}`.", token),
+            ParseError::Unclosed => write!(f, "parsing list failed: list is not closed."),
+            ParseError::MissingDatum(name) => write!(f, "missing expr next to the {}.", name),
+            ParseError::QuasiquoteNotList => write!(f, "quasiquote must exists before a list."),
+            ParseError::BadDottedList => {
+                write!(f, "parsing list failed: `.` must be followed by one expr.")
+            }
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// コンパイル（マクロ展開を含む）のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// 特殊形式の引数が足りない
+    ShortageOfArgs(&'static str),
+    /// 特殊形式の第一引数がシンボルでない
+    NotIdentifier(&'static str),
+    /// `define` の第一引数がシンボルでも、シンボルで始まるリストでもない
+    BadDefineTarget,
+    /// 構文キーワードを変数として参照した
+    SyntaxAsVariable(String),
+    /// 構文キーワードに `set!` した
+    SetSyntax(String),
+    /// 空のリストを評価しようとした
+    EmptyApplication,
+    /// 不完全なリストを評価しようとした
+    ImproperList,
+    /// 評価できない値を評価しようとした
+    InvalidExpr(String),
+    /// `let-syntax` の束縛が不正
+    BadSyntaxBinding,
+    /// マクロの変換器が `syntax-rules` でない
+    BadTransformer,
+    /// マクロが導入した識別子が、そのマクロの定義のスコープの外で使われた
+    MacroScope,
+    /// `syntax-rules` の定義や展開のエラー
+    SyntaxRules(&'static str),
+    /// `define-macro` によるマクロに渡した引数の個数が合わない
+    WrongMacroArgCount {
+        name: String,
+        expected: Arity,
+        given: usize,
+    },
+    /// `define-macro` によるマクロの展開中の実行時エラー
+    MacroExpansion(Box<RuntimeError>),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::ShortageOfArgs(form) => write!(f, "shortage of the args of `{}`.", form),
+            CompileError::NotIdentifier(form) => {
+                write!(f, "can accept only symbol as first arg of `{}`.", form)
+            }
+            CompileError::BadDefineTarget => {
+                write!(
+                    f,
+                    "can accept only symbol or (name args ...) as first arg of `define`."
+                )
+            }
+            CompileError::SyntaxAsVariable(name) => {
+                write!(f, "syntax keyword can't be used as a variable: {}", name)
+            }
+            CompileError::SetSyntax(name) => write!(f, "can't `set!` to syntax keyword: {}", name),
+            CompileError::EmptyApplication => write!(f, "attempt to evaluate nil."),
+            CompileError::ImproperList => write!(f, "attempt to evaluate improper list."),
+            CompileError::InvalidExpr(expr) => write!(f, "attempt to evaluate {}.", expr),
+            CompileError::BadSyntaxBinding => {
+                write!(f, "each binding of `let-syntax` must be (name spec).")
+            }
+            CompileError::BadTransformer => write!(f, "macro transformer must be `syntax-rules`."),
+            CompileError::MacroScope => {
+                write!(f, "identifier introduced by macro is out of its scope.")
+            }
+            CompileError::SyntaxRules(msg) => write!(f, "{}", msg),
+            CompileError::WrongMacroArgCount {
+                name,
+                expected,
+                given,
+            } => write!(
+                f,
+                "wrong number of arguments for macro {}: expected {}, given {}",
+                name, expected, given
+            ),
+            CompileError::MacroExpansion(err) => write!(f, "macro expansion failed: {}", err),
+        }
+    }
+}
+
+impl error::Error for CompileError {}
+
+impl From<CompileError> for Located<CompileError> {
+    fn from(error: CompileError) -> Self {
+        Located::new(error, None)
+    }
+}
+
+/// Scheme の値を Rust の値に変換できない
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub expected: String,
+    pub given: String,
+}
+
+impl TypeError {
+    pub fn new(expected: &str, given: &Node) -> Self {
+        TypeError {
+            expected: expected.to_string(),
+            given: given.inspect(),
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, given {}", self.expected, self.given)
+    }
+}
+
+impl error::Error for TypeError {}
+
+/// 実行時のエラー
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    /// 大域環境にない変数を参照した
+    UnboundVariable(String),
+    /// 局所変数の値がない（引数の不足など）
+    UnboundLocal(usize, isize),
+    /// 手続きでない値を適用しようとした
+    NotProcedure(String),
+    /// `define-macro` の値が lambda 式でない
+    NotMacro(String),
+    /// 手続きに渡した引数の個数が合わない
+    WrongArgCount {
+        name: String,
+        expected: Arity,
+        given: usize,
+    },
+    /// 手続きに渡した引数の型が合わない（位置は 1 始まり）
+    WrongType {
+        name: String,
+        position: usize,
+        error: TypeError,
+    },
+    /// 手続きに渡した引数が受け付ける範囲にない（位置は 1 始まり）
+    OutOfRange {
+        name: String,
+        position: usize,
+        given: String,
+    },
+    /// 組み込み手続きが報告したエラー
+    Primitive(String),
+    /// `exit` による実行の終了。エラーではないが、同じ経路で呼び出し元に伝える
+    Exit(i32),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::UnboundVariable(name) => {
+                write!(f, "symbol not found in the global environment: {}", name)
+            }
+            RuntimeError::UnboundLocal(i, j) => {
+                write!(f, "local variable ({}, {}) is not bound.", i, j)
+            }
+            RuntimeError::NotProcedure(obj) => write!(f, "attempt to apply non-procedure: {}", obj),
+            RuntimeError::NotMacro(obj) => {
+                write!(f, "`define-macro` requires lambda expression: {}", obj)
+            }
+            RuntimeError::WrongArgCount {
+                name,
+                expected,
+                given,
+            } => write!(
+                f,
+                "wrong number of arguments for {}: expected {}, given {}",
+                name, expected, given
+            ),
+            RuntimeError::WrongType {
+                name,
+                position,
+                error,
+            } => write!(f, "{}: argument {}: {}", name, position, error),
+            RuntimeError::OutOfRange {
+                name,
+                position,
+                given,
+            } => write!(
+                f,
+                "{}: argument {}: out of range: {}",
+                name, position, given
+            ),
+            RuntimeError::Primitive(msg) => write!(f, "{}", msg),
+            RuntimeError::Exit(status) => write!(f, "exit with status {}", status),
+        }
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+/// 処理系全体のエラー。組み込み用の API が返す
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// ファイルの読み込みの失敗
+    Io(String),
+    Parse(Located<ParseError>),
+    Compile(Located<CompileError>),
+    Runtime(Located<RuntimeError>),
+}
+
+impl Error {
+    /// エラーの発生位置
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Io(_) => None,
+            Error::Parse(err) => err.span,
+            Error::Compile(err) => err.span,
+            Error::Runtime(err) => err.span,
+        }
+    }
+
+    /// `source` の中での位置を添えてエラーを整形する
+    pub fn describe(&self, source: &Source) -> String {
+        match self {
+            Error::Io(msg) => format!("io error: {}", msg),
+            Error::Parse(err) => format!("parse error: {}", source.describe(&err.error, err.span)),
+            Error::Compile(err) => {
+                format!("compile error: {}", source.describe(&err.error, err.span))
+            }
+            Error::Runtime(err) => {
+                format!("runtime error: {}", source.describe(&err.error, err.span))
+            }
+        }
+    }
+
+    /// `exit` で実行が終了したのであれば、その終了ステータスを返す
+    pub fn exit_status(&self) -> Option<i32> {
+        match self {
+            Error::Runtime(Located {
+                error: RuntimeError::Exit(status),
+                ..
+            }) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (kind, msg): (&str, &dyn fmt::Display) = match self {
+            Error::Io(msg) => return write!(f, "io error: {}", msg),
+            Error::Parse(err) => ("parse error", &err.error),
+            Error::Compile(err) => ("compile error", &err.error),
+            Error::Runtime(err) => ("runtime error", &err.error),
+        };
+        match self.span() {
+            Some(span) => write!(f, "{} at {}: {}", kind, span, msg),
+            None => write!(f, "{}: {}", kind, msg),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Located<ParseError>> for Error {
+    fn from(err: Located<ParseError>) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<Located<CompileError>> for Error {
+    fn from(err: Located<CompileError>) -> Self {
+        Error::Compile(err)
+    }
+}
+
+impl From<Located<RuntimeError>> for Error {
+    fn from(err: Located<RuntimeError>) -> Self {
+        Error::Runtime(err)
+    }
+}
@@ -1,26 +1,178 @@
+use std::io::{self, Read};
 use std::path::PathBuf;
 
-use crate::env::init_global_env;
-use crate::util::get_source;
-
-pub fn exec(path_: &PathBuf, sources: Vec<String>) {
-    let mut sources = sources;
-    if path_.exists() {
-        if path_.is_file() {
-            match get_source(&path_) {
-                Ok(source) => sources.push(source),
-                Err(msg) => {
-                    eprintln!("{}", msg);
-                    std::process::exit(1);
+use crate::{
+    ast::{Arity, Node},
+    compiler::Compiler,
+    convert::ToScheme,
+    disasm::disassemble,
+    env::init_global_env,
+    interpreter::Interpreter,
+    lexer::Lexer,
+    parser::Parser,
+    span::Source,
+    symbol::Symbol,
+    util::get_source,
+    vm::VM,
+};
+
+/// 実行するプログラムの読み込み元
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    File(PathBuf),
+    /// 標準入力（コマンドラインでは `-`）
+    Stdin,
+    /// `-e` で与えた式。値を表示する
+    Expr(String),
+}
+
+impl Input {
+    /// `(command-line)` の先頭に置くプログラムの名前
+    fn name(&self) -> String {
+        match self {
+            Input::File(path) => path.display().to_string(),
+            Input::Stdin => "-".to_string(),
+            Input::Expr(_) => "-e".to_string(),
+        }
+    }
+
+    fn read(&self) -> Result<String, String> {
+        match self {
+            Input::File(path) => {
+                if !path.exists() {
+                    Err(format!("INPUT doesn't exist: {}", path.display()))
+                } else if !path.is_file() {
+                    Err(format!("INPUT is not file: {}", path.display()))
+                } else {
+                    get_source(path).map(skip_shebang)
                 }
             }
-        } else {
-            eprintln!("INPUT is not file: {}", path_.display());
-            std::process::exit(1);
+            Input::Stdin => {
+                let mut text = String::new();
+                io::stdin()
+                    .read_to_string(&mut text)
+                    .map_err(|err| format!("couldn't read stdin: {}", err))?;
+                Ok(text)
+            }
+            Input::Expr(expr) => Ok(expr.clone()),
+        }
+    }
+}
+
+/// スクリプトの先頭にある `#!` の行を空行に置き換える。行番号はそのまま保つ
+fn skip_shebang(text: String) -> String {
+    if text.starts_with("#!") {
+        match text.find('\n') {
+            Some(end) => text[end..].to_string(),
+            None => String::new(),
         }
     } else {
-        eprintln!("INPUT doesn't exist: {}", path_.display());
-        std::process::exit(1);
+        text
+    }
+}
+
+/// プログラムを実行する。`args` は `(command-line)` でプログラムの名前に続けて返す。
+/// エラーは位置とともに標準エラー出力に報告し、0 以外の終了ステータスで終了する
+pub fn exec(input: Input, sources: Vec<String>, args: Vec<String>) {
+    let text = match input.read() {
+        Ok(text) => text,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+    };
+
+    let mut interp = Interpreter::new();
+    for source in sources {
+        run(&mut interp, &Source::new("<load>", source));
+    }
+    let mut command_line = vec![input.name()];
+    command_line.extend(args);
+    interp.register_fn("command-line", Arity::Exact(0), move |_| {
+        Ok(command_line.clone().to_scheme())
+    });
+
+    let result = run(&mut interp, &Source::new(input.name(), text));
+    if matches!(input, Input::Expr(_)) && result != Node::Undef {
+        println!("{}", result.inspect());
+    }
+}
+
+/// ソースコードを評価して最後の式の値を返す。
+/// 失敗するか `exit` が呼ばれればプロセスを終了する
+fn run(interp: &mut Interpreter, source: &Source) -> Node {
+    match interp.eval_str(source.text()) {
+        Ok(result) => result,
+        Err(err) => match err.exit_status() {
+            Some(status) => std::process::exit(status),
+            None => {
+                eprintln!("{}", err.describe(source));
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+/// プログラム中の各式をコンパイルし、逆アセンブルした結果を表示する。
+/// 後に続く式を展開できるよう、マクロの定義だけは実行する
+pub fn disasm(input: Input, sources: Vec<String>) {
+    let source = match input.read() {
+        Ok(text) => Source::new(input.name(), text),
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        }
+    };
+    let mut global_env = init_global_env(Some(sources));
+
+    let mut p = Parser::new(Lexer::new(source.text()));
+    let nodes = match p.parse() {
+        Ok(nodes) => nodes,
+        Err(err) => {
+            eprintln!("parse error: {}", source.describe(err.error, err.span));
+            std::process::exit(1);
+        }
+    };
+    let spans = p.into_spans();
+    for (i, node) in nodes.into_iter().enumerate() {
+        let is_macro_def = matches!(
+            node.car(),
+            Some(Node::Ident(Symbol::DEFINE_SYNTAX | Symbol::DEFINE_MACRO))
+        );
+        let code = match Compiler::with_spans(node, &spans).compile(&mut global_env) {
+            Ok(code) => code,
+            Err(err) => {
+                eprintln!("compile error: {}", source.describe(err.error, err.span));
+                std::process::exit(1);
+            }
+        };
+        if 0 < i {
+            println!();
+        }
+        print!("{}", disassemble(&code));
+        if is_macro_def {
+            if let Err(err) = VM::new(code).run(&mut global_env) {
+                eprintln!("runtime error: {}", source.describe(err.error, err.span));
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod exec_test {
+    use super::skip_shebang;
+    use crate::{lexer::Lexer, span::Span, token::Token};
+
+    #[test]
+    fn skip_shebang_test() {
+        let text = skip_shebang("#!/usr/bin/env fzscheme\n(f #t)".to_string());
+        let toks: Vec<(Token, Span)> = Lexer::new(&text).spanned().collect();
+        assert_eq!(toks[0], (Token::Lparen, Span::new(2, 1)));
+        assert_eq!(toks.len(), 4);
+
+        assert_eq!(skip_shebang("#!x".to_string()), "");
+        // 先頭以外の `#!` はそのまま残す
+        assert_eq!(skip_shebang(" #!x".to_string()), " #!x");
     }
-    init_global_env(Some(sources));
 }
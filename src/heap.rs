@@ -0,0 +1,318 @@
+use std::cell::RefCell;
+use std::collections::{hash_map::Entry, HashMap};
+use std::rc::{Rc, Weak};
+
+use crate::{
+    ast::{Node, Pair},
+    env::Env,
+    vm::ContinuationData,
+};
+
+// この数だけフレームを作るたびに回収を試みる（生き残りが多ければ間隔を広げる）
+const INITIAL_THRESHOLD: usize = 10000;
+
+/// ヒープの統計情報
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// 現在生きているフレームの数
+    pub frames: usize,
+    /// これまでに作られたフレームの数
+    pub allocated: usize,
+    /// 回収を行った回数
+    pub collections: usize,
+    /// これまでに回収したフレームの数
+    pub freed: usize,
+}
+
+/// 実行時に作られる環境のフレームを追跡し、参照カウントでは解放されない循環を回収する。
+///
+/// 根を列挙する代わりに、追跡しているオブジェクトどうしの参照の数を参照カウントから差し引き、
+/// 外部からの参照が残っていないものをごみとみなす（試行削除）。
+/// 見えない参照は生きている側に数えられるだけなので、組み込み側が値を保持していても安全に回収できる
+struct Heap {
+    frames: Vec<Weak<RefCell<Env>>>,
+    allocated_since_gc: usize,
+    threshold: usize,
+    stats: HeapStats,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        frames: Vec::new(),
+        allocated_since_gc: 0,
+        threshold: INITIAL_THRESHOLD,
+        stats: HeapStats::default(),
+    });
+}
+
+/// 実行時のフレームを作ってヒープに登録する。必要であれば先に回収を行う
+pub fn alloc_env(env: Env) -> Rc<RefCell<Env>> {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        if heap.threshold <= heap.allocated_since_gc {
+            let survivors = heap.collect().1;
+            heap.threshold = INITIAL_THRESHOLD.max(survivors * 2);
+        }
+        let env = Rc::new(RefCell::new(env));
+        heap.frames.push(Rc::downgrade(&env));
+        heap.allocated_since_gc += 1;
+        heap.stats.allocated += 1;
+        env
+    })
+}
+
+/// 循環参照によって残っているフレームを回収し、回収したフレームの数を返す
+pub fn collect() -> usize {
+    HEAP.with(|heap| heap.borrow_mut().collect().0)
+}
+
+pub fn stats() -> HeapStats {
+    HEAP.with(|heap| {
+        let heap = heap.borrow();
+        HeapStats {
+            frames: heap.frames.iter().filter(|w| w.strong_count() > 0).count(),
+            ..heap.stats
+        }
+    })
+}
+
+/// 回収の対象となるオブジェクト
+enum Obj {
+    Frame(Rc<RefCell<Env>>),
+    Pair(Rc<Pair>),
+    // 複数の値から共有されるので、中の参照を一度だけ数えるために独立して扱う
+    Continuation(Rc<ContinuationData>),
+}
+
+/// オブジェクトが直接保持している参照
+enum Edge<'a> {
+    Frame(&'a Rc<RefCell<Env>>),
+    Pair(&'a Rc<Pair>),
+    Continuation(&'a Rc<ContinuationData>),
+}
+
+impl Obj {
+    fn id(&self) -> usize {
+        match self {
+            Obj::Frame(env) => Rc::as_ptr(env) as usize,
+            Obj::Pair(pair) => Rc::as_ptr(pair) as usize,
+            Obj::Continuation(cont) => Rc::as_ptr(cont) as usize,
+        }
+    }
+
+    /// ヒープが持っている分を除いた参照カウント
+    fn ref_count(&self) -> usize {
+        match self {
+            Obj::Frame(env) => Rc::strong_count(env) - 1,
+            Obj::Pair(pair) => Rc::strong_count(pair) - 1,
+            Obj::Continuation(cont) => Rc::strong_count(cont) - 1,
+        }
+    }
+
+    fn for_each_edge(&self, f: &mut dyn FnMut(Edge)) {
+        match self {
+            Obj::Frame(env) => {
+                let env = env.borrow();
+                for node in env.slots() {
+                    visit_node(node, f);
+                }
+                if let Some(next_env) = env.next_env() {
+                    f(Edge::Frame(next_env));
+                }
+            }
+            Obj::Pair(pair) => {
+                let (car, cdr) = pair.fields();
+                visit_node(&car, f);
+                visit_node(&cdr, f);
+            }
+            Obj::Continuation(cont) => {
+                for item in cont.stack.iter() {
+                    visit_node(item.node(), f);
+                }
+                f(Edge::Frame(&cont.saved.env));
+                for item in cont.dump.iter() {
+                    f(Edge::Frame(&item.env));
+                }
+            }
+        }
+    }
+
+    fn clear(&self) {
+        match self {
+            Obj::Frame(env) => env.borrow_mut().clear(),
+            Obj::Pair(pair) => pair.clear(),
+            // 継続は変更できないが、循環には必ずフレームかペアが含まれるので、
+            // それらを断ち切れば解放される
+            Obj::Continuation(_) => (),
+        }
+    }
+}
+
+fn edge_id(edge: &Edge) -> usize {
+    match edge {
+        Edge::Frame(env) => Rc::as_ptr(env) as usize,
+        Edge::Pair(pair) => Rc::as_ptr(pair) as usize,
+        Edge::Continuation(cont) => Rc::as_ptr(cont) as usize,
+    }
+}
+
+/// 値が直接保持しているフレーム、ペアおよび継続をたどる
+fn visit_node(node: &Node, f: &mut dyn FnMut(Edge)) {
+    match node {
+        Node::Pair(pair) => f(Edge::Pair(pair)),
+        Node::Closure(_, env) => f(Edge::Frame(env)),
+        Node::Continuation(cont) => f(Edge::Continuation(cont)),
+        _ => (),
+    }
+}
+
+impl Heap {
+    /// 回収したフレームの数と生き残ったフレームの数を返す
+    fn collect(&mut self) -> (usize, usize) {
+        self.frames.retain(|w| w.strong_count() > 0);
+        self.allocated_since_gc = 0;
+        self.stats.collections += 1;
+
+        // 追跡しているフレームと、そこからたどれるペアと継続を対象にする
+        let mut objs: Vec<Obj> = self
+            .frames
+            .iter()
+            .filter_map(|w| w.upgrade().map(Obj::Frame))
+            .collect();
+        let mut index: HashMap<usize, usize> = objs
+            .iter()
+            .enumerate()
+            .map(|(i, obj)| (obj.id(), i))
+            .collect();
+        let mut i = 0;
+        while i < objs.len() {
+            let base = objs.len();
+            let mut found = Vec::new();
+            objs[i].for_each_edge(&mut |edge| {
+                if let Entry::Vacant(entry) = index.entry(edge_id(&edge)) {
+                    let obj = match edge {
+                        Edge::Frame(_) => return,
+                        Edge::Pair(pair) => Obj::Pair(pair.clone()),
+                        Edge::Continuation(cont) => Obj::Continuation(cont.clone()),
+                    };
+                    entry.insert(base + found.len());
+                    found.push(obj);
+                }
+            });
+            objs.extend(found);
+            i += 1;
+        }
+
+        // 対象どうしの参照を差し引き、外から参照されているものを求める
+        let mut refs: Vec<usize> = objs.iter().map(Obj::ref_count).collect();
+        for obj in &objs {
+            obj.for_each_edge(&mut |edge| {
+                if let Some(&j) = index.get(&edge_id(&edge)) {
+                    refs[j] -= 1;
+                }
+            });
+        }
+
+        // 外から参照されているものとそこからたどれるものは生きている
+        let mut live = vec![false; objs.len()];
+        let mut work: Vec<usize> = (0..objs.len()).filter(|&i| refs[i] > 0).collect();
+        while let Some(i) = work.pop() {
+            if live[i] {
+                continue;
+            }
+            live[i] = true;
+            objs[i].for_each_edge(&mut |edge| {
+                if let Some(&j) = index.get(&edge_id(&edge)) {
+                    if !live[j] {
+                        work.push(j);
+                    }
+                }
+            });
+        }
+
+        // 残りはごみなので、互いへの参照を断ち切って解放させる
+        let mut freed = 0;
+        let mut survivors = 0;
+        for (obj, live) in objs.iter().zip(&live) {
+            match (obj, live) {
+                (Obj::Frame(_), true) => survivors += 1,
+                (Obj::Frame(_), false) => freed += 1,
+                _ => (),
+            }
+            if !live {
+                obj.clear();
+            }
+        }
+        drop(objs);
+        self.frames.retain(|w| w.strong_count() > 0);
+        self.stats.freed += freed;
+        (freed, survivors)
+    }
+}
+
+#[cfg(test)]
+mod heap_test {
+    use super::{collect, stats};
+    use crate::{ast::Node, interpreter::Interpreter};
+
+    #[test]
+    fn collect_cycle_test() {
+        let mut interp = Interpreter::new();
+        interp
+            .eval_str("(define (make) (letrec ((f (lambda () f))) f)) (define keep (make))")
+            .unwrap();
+        collect();
+        let before = stats();
+
+        // letrec で作った手続きは自身を定義したフレームに格納されて循環し、
+        // make の呼び出しのフレームとともに残る
+        interp.eval_str("(make) (make) (make)").unwrap();
+        assert_eq!(stats().frames, before.frames + 6);
+        assert_eq!(collect(), 6);
+        assert_eq!(stats().frames, before.frames);
+
+        // 大域変数から参照されているものは回収されない
+        assert_eq!(interp.eval_str("(eq? (keep) keep)"), Ok(Node::Bool(true)));
+
+        // 組み込み側が保持している値も回収されない
+        let held = interp.eval_str("(make)").unwrap();
+        collect();
+        interp.set_global("held", held);
+        assert_eq!(interp.eval_str("(eq? (held) held)"), Ok(Node::Bool(true)));
+    }
+
+    #[test]
+    fn shared_continuation_test() {
+        let mut interp = Interpreter::new();
+        let source = "
+(define saved #f)
+(define (capture n) (+ n (call/cc (lambda (k) (set! saved k) 0))))
+(capture 1)
+(define (mk x) (letrec ((f (lambda () x))) f))";
+        interp.eval_str(source).unwrap();
+        collect();
+        let before = stats();
+
+        // 二つの循環が同じ継続を参照していても、継続の中の参照は一度だけ差し引く
+        interp.eval_str("(mk saved) (mk saved)").unwrap();
+        assert_eq!(collect(), 4);
+
+        // 大域変数から参照されている継続が捕捉したフレームは残る
+        assert_eq!(stats().frames, before.frames);
+        assert!(before.frames > 0);
+    }
+
+    #[test]
+    fn gc_primitive_test() {
+        let mut interp = Interpreter::new();
+        let source = "
+(define (make) (letrec ((f (lambda () f))) f))
+(define (loop n) (if (= n 0) 'done (begin (make) (loop (- n 1)))))
+(loop 5)
+(gc)";
+        assert_eq!(interp.eval_str(source), Ok(Node::Int(10)));
+        let result = interp.eval_str("(heap-stats)").unwrap();
+        assert_eq!(result.car().unwrap().inspect(), "(frames . 0)");
+        assert_eq!(stats().freed, 10);
+    }
+}
@@ -1,22 +1,44 @@
-use std::collections::LinkedList;
+use std::rc::Rc;
 
-use crate::ast::Node;
+use crate::{
+    ast::{Arity, Node},
+    span::Span,
+    symbol::Symbol,
+};
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// 手続き一つ分の平坦な命令列。実行位置はプログラムカウンタで指す
+pub type Code = Rc<[Inst]>;
+
+/// コンパイル済みの lambda 式
+#[derive(Debug, PartialEq)]
+pub struct Lambda {
+    pub code: Code,
+    pub arity: Arity,
+    /// 表示用の仮引数リスト
+    pub params: Node,
+    /// `define` で束縛した名前。エラーで手続きを示すのに使う
+    pub name: Option<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Inst {
     Ld(usize, isize),
     Ldc(Node),
-    Ldg(Node),
-    Ldf(LinkedList<Inst>),
+    Ldg(Symbol),
+    Ldf(Rc<Lambda>),
     Lset(usize, isize),
-    Gset(Node),
+    Gset(Symbol),
     Args(usize),
     App,
+    TApp,
     Rtn,
-    Sel(LinkedList<Inst>, LinkedList<Inst>),
-    Join,
+    /// スタックトップが `#f` であれば、同じ命令列の指定の位置へ飛ぶ
+    Jf(usize),
+    /// 同じ命令列の指定の位置へ飛ぶ
+    Jmp(usize),
     Pop,
-    Def(Node),
-    Defm(Node),
+    Def(Symbol),
+    Defm(Symbol),
     Stop,
+    Loc(Span),
 }
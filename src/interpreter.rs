@@ -0,0 +1,289 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::{
+    ast::{Arity, Node, Primitive},
+    compiler::Compiler,
+    convert::{ToScheme, TypedFn},
+    env::{init_global_env, GlobalEnv},
+    error::{Error, RuntimeError},
+    heap::{self, HeapStats},
+    lexer::Lexer,
+    parser::Parser,
+    symbol::Symbol,
+    util::get_source,
+    vm::{tag_of, StackItem, VM},
+};
+
+/// 組み込み用の API でやり取りする Scheme の値
+pub type Value = Node;
+
+/// Rust のプログラムから Scheme のコードを評価するための処理系
+#[derive(Debug)]
+pub struct Interpreter {
+    global_env: GlobalEnv,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter {
+            global_env: init_global_env(None),
+        }
+    }
+
+    /// ソースコード中の式を順に評価し、最後の式の値を返す。
+    /// 式がなければ未定義値を返す
+    pub fn eval_str(&mut self, src: &str) -> Result<Value, Error> {
+        let mut p = Parser::new(Lexer::new(src));
+        let nodes = p.parse()?;
+        let spans = p.into_spans();
+        let mut result = Node::Undef;
+        for node in nodes {
+            let code = Compiler::with_spans(node, &spans).compile(&mut self.global_env)?;
+            result = VM::new(code).run(&mut self.global_env)?;
+        }
+        Ok(result)
+    }
+
+    /// ファイルの内容を評価し、最後の式の値を返す
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Value, Error> {
+        let source = get_source(&path.as_ref().to_path_buf()).map_err(Error::Io)?;
+        self.eval_str(&source)
+    }
+
+    /// 大域変数の値を返す。マクロや構文キーワードであれば `None` を返す
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        match self.global_env.get(Symbol::intern(name))? {
+            StackItem::Other(Node::Macro(_) | Node::Syntax(_)) => None,
+            StackItem::Closure(node)
+            | StackItem::Primitive(node)
+            | StackItem::Continuation(node)
+            | StackItem::Other(node) => Some(node.clone()),
+        }
+    }
+
+    /// 大域変数を定義する。すでに定義されていれば値を置き換える
+    pub fn set_global<T: ToScheme>(&mut self, name: &str, value: T) {
+        let value = value.to_scheme();
+        let tag = tag_of(&value);
+        self.global_env
+            .insert(Symbol::intern(name), StackItem::new(value, tag));
+    }
+
+    /// Rust のクロージャを組み込み手続きとして大域変数に定義する。
+    /// 引数の個数は呼び出し前に `arity` で検査される
+    pub fn register_fn<F>(&mut self, name: &str, arity: Arity, func: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let prim = Primitive::new(name, arity, func);
+        self.set_global(name, Node::Primitive(Rc::new(prim)));
+    }
+
+    /// 循環参照で残っている環境のフレームを回収し、回収した数を返す
+    pub fn gc(&mut self) -> usize {
+        heap::collect()
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        heap::stats()
+    }
+
+    /// 引数と戻り値を自動で変換する Rust の関数を組み込み手続きとして定義する。
+    /// 引数の型が合わなければ `RuntimeError::WrongType` になる
+    pub fn register_typed_fn<Args, F: TypedFn<Args>>(&mut self, name: &str, func: F) {
+        let prim = func.into_primitive(name);
+        self.set_global(name, Node::Primitive(Rc::new(prim)));
+    }
+}
+
+#[cfg(test)]
+mod interpreter_test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::Interpreter;
+    use crate::{
+        ast::{Arity, Node},
+        error::{Error, RuntimeError},
+        span::Span,
+    };
+
+    #[test]
+    fn eval_str_test() {
+        let mut interp = Interpreter::new();
+        let result = interp.eval_str("(define (square x) (* x x)) (square 12)");
+        assert_eq!(result, Ok(Node::Int(144)));
+        assert_eq!(interp.eval_str(""), Ok(Node::Undef));
+
+        // エラーの後も処理系は使い続けられる
+        let err = interp.eval_str("(square 1)\n(car (square 2))").unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::Runtime(err)
+                if err.span == Some(Span::new(2, 1))
+                    && matches!(err.error, RuntimeError::WrongType { .. })
+        ));
+        assert!(matches!(interp.eval_str("(square"), Err(Error::Parse(_))));
+        assert!(matches!(interp.eval_str("(if)"), Err(Error::Compile(_))));
+        assert_eq!(interp.eval_str("(square 3)"), Ok(Node::Int(9)));
+    }
+
+    #[test]
+    fn global_test() {
+        let mut interp = Interpreter::new();
+        interp.set_global("limit", Node::Int(10));
+        assert_eq!(interp.eval_str("(* limit 2)"), Ok(Node::Int(20)));
+
+        interp.eval_str("(define greeting \"hello\")").unwrap();
+        assert_eq!(
+            interp.get_global("greeting"),
+            Some(Node::Str("hello".to_string()))
+        );
+        assert_eq!(interp.get_global("undefined-var"), None);
+        assert_eq!(interp.get_global("let"), None);
+
+        // 手続きを別の名前で登録し直しても呼び出せる
+        let car = interp.get_global("car").unwrap();
+        interp.set_global("first", car);
+        assert_eq!(interp.eval_str("(first '(1 2))"), Ok(Node::Int(1)));
+    }
+
+    #[test]
+    fn register_fn_test() {
+        let mut interp = Interpreter::new();
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        interp.register_fn("add1", Arity::Exact(1), move |args| {
+            counter.set(counter.get() + 1);
+            match &args[0] {
+                Node::Int(n) => Ok(Node::Int(n + 1)),
+                other => Err(RuntimeError::Primitive(format!(
+                    "add1: not integer: {}",
+                    other.inspect()
+                ))),
+            }
+        });
+        assert_eq!(interp.eval_str("(add1 (add1 1))"), Ok(Node::Int(3)));
+        assert_eq!(calls.get(), 2);
+
+        // 高階手続きに渡したり、手続きを返したりできる
+        interp.eval_str("(define (twice f x) (f (f x)))").unwrap();
+        assert_eq!(interp.eval_str("(twice add1 5)"), Ok(Node::Int(7)));
+        interp.register_fn("get-add1", Arity::Exact(0), {
+            let interp_add1 = interp.get_global("add1").unwrap();
+            move |_| Ok(interp_add1.clone())
+        });
+        assert_eq!(interp.eval_str("((get-add1) 10)"), Ok(Node::Int(11)));
+
+        // 同じ実体どうしは等しい
+        assert_eq!(interp.get_global("add1"), interp.get_global("add1"));
+        assert_ne!(interp.get_global("add1"), interp.get_global("car"));
+        assert_eq!(interp.eval_str("(eq? add1 add1)"), Ok(Node::Bool(true)));
+        assert!(format!("{:?}", interp.get_global("add1").unwrap()).contains("add1"));
+
+        let err = interp.eval_str("(add1 \"a\")").unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::Runtime(err) if err.error == RuntimeError::Primitive("add1: not integer: \"a\"".to_string())
+        ));
+        let err = interp.eval_str("(add1)").unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::Runtime(err) if matches!(err.error, RuntimeError::WrongArgCount { given: 0, .. })
+        ));
+
+        // 名前が call/cc でも、登録した関数は普通の手続きとして呼ばれる
+        interp.register_fn("call/cc", Arity::Exact(1), |args| Ok(args[0].clone()));
+        assert_eq!(interp.eval_str("(call/cc 1)"), Ok(Node::Int(1)));
+    }
+
+    #[test]
+    fn register_typed_fn_test() {
+        let mut interp = Interpreter::new();
+        interp.register_typed_fn("long-name?", |len: i64, name: String| {
+            name.chars().count() as i64 > len
+        });
+        interp.register_typed_fn("sum", |nums: Vec<i64>| nums.iter().sum::<i64>());
+        interp.register_typed_fn("checked-div", |a: i64, b: i64| {
+            a.checked_div(b)
+                .ok_or_else(|| RuntimeError::Primitive("checked-div: division by zero".to_string()))
+        });
+        interp.set_global("names", vec!["alice", "bob"]);
+
+        assert_eq!(
+            interp.eval_str("(long-name? 3 (car names))"),
+            Ok(Node::Bool(true))
+        );
+        assert_eq!(interp.eval_str("(sum '(1 2 3))"), Ok(Node::Int(6)));
+        assert_eq!(interp.eval_str("(checked-div 7 2)"), Ok(Node::Int(3)));
+        assert!(matches!(
+            interp.eval_str("(checked-div 1 0)"),
+            Err(Error::Runtime(err)) if matches!(err.error, RuntimeError::Primitive(_))
+        ));
+
+        let err = interp.eval_str("(long-name? 3 'bob)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "runtime error at 1:1: long-name?: argument 2: expected string, given bob"
+        );
+        let err = interp.eval_str("(sum '(1 \"2\"))").unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::Runtime(err) if matches!(&err.error, RuntimeError::WrongType { position: 1, .. })
+        ));
+        assert!(matches!(
+            interp.eval_str("(long-name? 3)"),
+            Err(Error::Runtime(err)) if matches!(err.error, RuntimeError::WrongArgCount { .. })
+        ));
+    }
+
+    #[test]
+    fn exit_test() {
+        let mut interp = Interpreter::new();
+        let cases = [
+            ("(exit)", 0),
+            ("(exit 3)", 3),
+            ("(exit #t)", 0),
+            ("(exit #f)", 1),
+        ];
+        for (source, expected) in cases {
+            let err = interp.eval_str(source).unwrap_err();
+            assert_eq!(err.exit_status(), Some(expected), "source: {}", source);
+        }
+
+        // exit の後の式は評価されない
+        interp
+            .eval_str("(define x 1) (exit 2) (define x 3)")
+            .unwrap_err();
+        assert_eq!(interp.get_global("x"), Some(Node::Int(1)));
+
+        let err = interp.eval_str("(exit \"x\")").unwrap_err();
+        assert_eq!(err.exit_status(), None);
+        assert!(matches!(
+            err,
+            Error::Runtime(err) if matches!(err.error, RuntimeError::Primitive(_))
+        ));
+    }
+
+    #[test]
+    fn load_file_test() {
+        let mut interp = Interpreter::new();
+        let path = std::env::temp_dir().join("fzscheme_load_file_test.scm");
+        std::fs::write(&path, "(define x 20)\n(+ x 1)\n").unwrap();
+        assert_eq!(interp.load_file(&path), Ok(Node::Int(21)));
+        assert_eq!(interp.get_global("x"), Some(Node::Int(20)));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            interp.load_file("/nonexistent/file.scm"),
+            Err(Error::Io(_))
+        ));
+    }
+}
@@ -1,22 +1,56 @@
 use std::iter::Peekable;
 use std::str::Chars;
 
-use crate::token::Token;
+use crate::{
+    ast::{Node, CHAR_NAMES},
+    error::LexError,
+    number,
+    span::Span,
+    token::Token,
+};
 
 #[derive(Debug)]
 pub struct Lexer<'a> {
     chars: Peekable<Chars<'a>>,
+    line: usize,
+    col: usize,
+    // 直前に読んだトークンの開始位置
+    start: Span,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(source: &'a str) -> Self {
+        Lexer::with_line(source, 1)
+    }
+
+    /// `line` 行目から始まるソースコードとして読む
+    pub fn with_line(source: &'a str, line: usize) -> Self {
         Lexer {
             chars: source.chars().peekable(),
+            line,
+            col: 1,
+            start: Span::new(line, 1),
+        }
+    }
+
+    /// トークンとその開始位置の組を返すイテレータに変換する
+    pub fn spanned(self) -> Spanned<'a> {
+        Spanned { lex: self }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        if ch == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
         }
+        Some(ch)
     }
 
     fn read_escape_char(&mut self) -> Result<char, ()> {
-        if let Some(ch) = self.chars.next() {
+        if let Some(ch) = self.bump() {
             match ch {
                 't' => Ok('\t'),
                 'n' => Ok('\n'),
@@ -32,7 +66,7 @@ impl<'a> Lexer<'a> {
 
     fn read_string_literal(&mut self) -> Result<String, String> {
         let mut s = String::new();
-        while let Some(ch) = self.chars.next() {
+        while let Some(ch) = self.bump() {
             if ch == '"' {
                 return Ok(s);
             } else if ch == '\\' {
@@ -51,7 +85,7 @@ impl<'a> Lexer<'a> {
     fn skip_whitespace(&mut self) {
         while let Some(ch) = self.chars.peek() {
             if ch.is_ascii_whitespace() {
-                self.chars.next();
+                self.bump();
             } else {
                 break;
             }
@@ -62,15 +96,72 @@ impl<'a> Lexer<'a> {
         if Some(&';') == self.chars.peek() {
             while let Some(ch) = self.chars.peek() {
                 if *ch == '\n' {
-                    self.chars.next();
+                    self.bump();
                     break;
                 } else {
-                    self.chars.next();
+                    self.bump();
                 }
             }
         }
     }
 
+    fn read_ident(&mut self, first: char) -> Token {
+        self.read_atom(first.to_string())
+    }
+
+    /// 区切り文字までを読み、数値または識別子のトークンにする
+    fn read_atom(&mut self, prefix: String) -> Token {
+        let mut ident_buf = prefix;
+        while let Some(ch) = self.chars.peek() {
+            if ch.is_ascii_whitespace() || *ch == ')' || *ch == ']' {
+                break;
+            } else if *ch == '(' || *ch == '[' {
+                return Token::Illegal(LexError::UnexpectedParen(ident_buf));
+            } else {
+                ident_buf.push(*ch);
+                self.bump();
+            }
+        }
+        if let Some(tok) = number_token(&ident_buf) {
+            tok
+        } else if ident_buf.starts_with(|ch: char| ch.is_ascii_digit()) {
+            Token::Illegal(LexError::InvalidNumber(ident_buf))
+        } else {
+            Token::Ident(ident_buf)
+        }
+    }
+
+    /// `#\` に続く文字の表記を読む。一文字目は区切り文字であってもその文字自身を表す
+    fn read_char_literal(&mut self) -> Token {
+        let first = match self.bump() {
+            Some(ch) => ch,
+            None => return Token::Illegal(LexError::InvalidHashSyntax("#\\".to_string())),
+        };
+        let mut name = first.to_string();
+        while let Some(ch) = self.chars.peek() {
+            if ch.is_whitespace() || matches!(ch, '(' | ')' | '[' | ']' | '"' | ';') {
+                break;
+            }
+            name.push(*ch);
+            self.bump();
+        }
+        if name.chars().count() == 1 {
+            return Token::Char(first);
+        }
+        let named = CHAR_NAMES
+            .iter()
+            .find(|(char_name, _)| *char_name == name)
+            .map(|(_, ch)| *ch);
+        let hex = name
+            .strip_prefix('x')
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32);
+        match named.or(hex) {
+            Some(ch) => Token::Char(ch),
+            None => Token::Illegal(LexError::InvalidHashSyntax(format!("#\\{}", name))),
+        }
+    }
+
     fn next_token(&mut self) -> Token {
         while let Some(ch) = self.chars.peek() {
             if ch.is_ascii_whitespace() || *ch == ';' {
@@ -81,83 +172,60 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        if let Some(ch) = self.chars.next() {
+        self.start = Span::new(self.line, self.col);
+        if let Some(ch) = self.bump() {
             match ch {
                 '(' => Token::Lparen,
                 ')' => Token::Rparen,
                 '[' => Token::Lbracket,
                 ']' => Token::Rbracket,
                 '\'' => Token::Quote,
-                '.' => Token::Dot,
+                '.' => match self.chars.peek() {
+                    // `...` などの `.` で始まる識別子や `.5` などの数値
+                    Some(ch)
+                        if !ch.is_ascii_whitespace() && !matches!(ch, '(' | ')' | '[' | ']') =>
+                    {
+                        self.read_ident('.')
+                    }
+                    _ => Token::Dot,
+                },
                 '`' => Token::Quasiquote,
                 ',' => {
                     if let Some(ch) = self.chars.peek() {
                         if *ch == '@' {
-                            self.chars.next();
+                            self.bump();
                             Token::UnquoteSplicing
                         } else {
                             Token::Unquote
                         }
                     } else {
-                        Token::Illegal
+                        Token::Illegal(LexError::DanglingComma)
                     }
                 }
                 '#' => {
-                    if let Some(ch) = self.chars.next() {
+                    if let Some(ch) = self.bump() {
                         match ch {
                             't' => Token::True,
                             'f' => Token::False,
-                            _ => Token::Illegal,
-                        }
-                    } else {
-                        Token::Illegal
-                    }
-                }
-                '0' => {
-                    if let Some(ch) = self.chars.peek() {
-                        if ch.is_ascii_whitespace() || *ch == ')' || *ch == ']' {
-                            Token::Integer(0)
-                        } else {
-                            Token::Illegal
+                            '\\' => self.read_char_literal(),
+                            'x' | 'X' | 'b' | 'B' | 'o' | 'O' | 'd' | 'D' | 'e' | 'E' | 'i'
+                            | 'I' => match self.read_atom(format!("#{}", ch)) {
+                                Token::Ident(ident) => {
+                                    Token::Illegal(LexError::InvalidHashSyntax(ident))
+                                }
+                                tok => tok,
+                            },
+                            _ => Token::Illegal(LexError::InvalidHashSyntax(format!("#{}", ch))),
                         }
                     } else {
-                        Token::Illegal
+                        Token::Illegal(LexError::InvalidHashSyntax("#".to_string()))
                     }
                 }
-                '1'..='9' => {
-                    let mut int_buf = String::new();
-                    int_buf.push(ch);
-                    while let Some(ch) = self.chars.peek() {
-                        if ch.is_ascii_digit() {
-                            int_buf.push(*ch);
-                            self.chars.next();
-                        } else if ch.is_ascii_whitespace() || *ch == ')' || *ch == ']' {
-                            break;
-                        } else {
-                            return Token::Illegal;
-                        }
-                    }
-                    Token::Integer(int_buf.parse().unwrap())
-                }
                 '"' => match self.read_string_literal() {
                     Ok(string) => Token::Str(string),
-                    Err(_) => Token::Illegal,
+                    Err(_) => Token::Illegal(LexError::UnterminatedString),
                 },
-                ch => {
-                    let mut ident_buf = String::new();
-                    ident_buf.push(ch);
-                    while let Some(ch) = self.chars.peek() {
-                        if ch.is_ascii_whitespace() || *ch == ')' || *ch == ']' {
-                            break;
-                        } else if *ch == '(' || *ch == '[' {
-                            return Token::Illegal;
-                        } else {
-                            ident_buf.push(*ch);
-                            self.chars.next();
-                        }
-                    }
-                    Token::Ident(ident_buf)
-                }
+                ch => self.read_ident(ch),
             }
         } else {
             Token::Eof
@@ -165,6 +233,32 @@ impl<'a> Lexer<'a> {
     }
 }
 
+/// トークンに開始位置を添えて返すイテレータ
+#[derive(Debug)]
+pub struct Spanned<'a> {
+    lex: Lexer<'a>,
+}
+
+impl<'a> Iterator for Spanned<'a> {
+    type Item = (Token, Span);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tok = self.lex.next()?;
+        Some((tok, self.lex.start))
+    }
+}
+
+/// 数値として解釈できる字句であれば数値のトークンを返す
+fn number_token(source: &str) -> Option<Token> {
+    match number::parse_number(source, 10)? {
+        Node::Int(int) => Some(Token::Integer(int)),
+        Node::BigInt(int) => Some(Token::BigInt(int)),
+        Node::Rational(rat) => Some(Token::Rational(rat)),
+        Node::Real(real) => Some(Token::Real(real)),
+        _ => None,
+    }
+}
+
 impl<'a> Iterator for Lexer<'a> {
     type Item = Token;
 
@@ -180,8 +274,11 @@ impl<'a> Iterator for Lexer<'a> {
 
 #[cfg(test)]
 mod lexer_test {
+    use num_bigint::BigInt;
+    use num_rational::BigRational;
+
     use super::Lexer;
-    use crate::token::Token;
+    use crate::{error::LexError, span::Span, token::Token};
 
     #[test]
     fn lex_sexp_test() {
@@ -269,4 +366,93 @@ mod lexer_test {
             );
         }
     }
+
+    #[test]
+    fn lex_number_test() {
+        let source = "(1.5 -3 1/3 1e10 #xff #b101 #o17 12abc #x#é #é)";
+        let expected = vec![
+            Token::Lparen,
+            Token::Real(1.5),
+            Token::Integer(-3),
+            Token::Rational(BigRational::new(BigInt::from(1), BigInt::from(3))),
+            Token::Real(1e10),
+            Token::Integer(255),
+            Token::Integer(5),
+            Token::Integer(15),
+            Token::Illegal(LexError::InvalidNumber("12abc".to_string())),
+            Token::Illegal(LexError::InvalidHashSyntax("#x#é".to_string())),
+            Token::Illegal(LexError::InvalidHashSyntax("#é".to_string())),
+        ];
+
+        let lex = Lexer::new(source);
+
+        for (tok, expected_tok) in lex.zip(expected) {
+            assert_eq!(
+                tok, expected_tok,
+                "expected: {:?}, got: {:?}",
+                expected_tok, tok
+            );
+        }
+    }
+
+    #[test]
+    fn lex_signed_number_test() {
+        let source = "(-5 007 +3 - + ->x ... -0 +12/4 -2.5)";
+        let expected = vec![
+            Token::Lparen,
+            Token::Integer(-5),
+            Token::Integer(7),
+            Token::Integer(3),
+            Token::Ident("-".to_string()),
+            Token::Ident("+".to_string()),
+            Token::Ident("->x".to_string()),
+            Token::Ident("...".to_string()),
+            Token::Integer(0),
+            Token::Integer(3),
+            Token::Real(-2.5),
+            Token::Rparen,
+        ];
+
+        let toks: Vec<Token> = Lexer::new(source).collect();
+        assert_eq!(toks, expected);
+    }
+
+    #[test]
+    fn lex_char_test() {
+        let source = r"(#\a #\A #\( #\) #\space #\newline #\x41 #\x #\あ #\foo)";
+        let expected = vec![
+            Token::Lparen,
+            Token::Char('a'),
+            Token::Char('A'),
+            Token::Char('('),
+            Token::Char(')'),
+            Token::Char(' '),
+            Token::Char('\n'),
+            Token::Char('A'),
+            Token::Char('x'),
+            Token::Char('あ'),
+            Token::Illegal(LexError::InvalidHashSyntax(r"#\foo".to_string())),
+            Token::Rparen,
+        ];
+
+        let toks: Vec<Token> = Lexer::new(source).collect();
+        assert_eq!(toks, expected);
+    }
+
+    #[test]
+    fn lex_span_test() {
+        let source = "(car\n  'x) ; comment\n\"a\nb\" 1";
+        let expected = vec![
+            (Token::Lparen, Span::new(1, 1)),
+            (Token::Ident("car".to_string()), Span::new(1, 2)),
+            (Token::Quote, Span::new(2, 3)),
+            (Token::Ident("x".to_string()), Span::new(2, 4)),
+            (Token::Rparen, Span::new(2, 5)),
+            (Token::Str("a\nb".to_string()), Span::new(3, 1)),
+            (Token::Integer(1), Span::new(4, 4)),
+        ];
+
+        let toks: Vec<(Token, Span)> = Lexer::new(source).spanned().collect();
+        assert_eq!(toks, expected);
+    }
 }
@@ -1,12 +1,26 @@
 pub mod ast;
 pub mod compiler;
+pub mod convert;
+pub mod disasm;
 pub mod env;
+pub mod error;
 pub mod exec;
+pub mod heap;
 pub mod inst;
+pub mod interpreter;
 pub mod lexer;
+pub mod number;
 pub mod parser;
 pub mod primitive;
 pub mod repl;
+pub mod span;
+pub mod symbol;
+pub mod syntax_rules;
 pub mod token;
 pub mod util;
 pub mod vm;
+
+pub use ast::Arity;
+pub use convert::{FromScheme, ToScheme};
+pub use error::{Error, RuntimeError, TypeError};
+pub use interpreter::{Interpreter, Value};
@@ -2,9 +2,9 @@ use std::path::PathBuf;
 
 #[macro_use]
 extern crate clap;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg};
 
-use rusty_fzscheme::exec::exec;
+use rusty_fzscheme::exec::{disasm, exec, Input};
 use rusty_fzscheme::repl::repl;
 use rusty_fzscheme::util::get_sources;
 
@@ -15,12 +15,29 @@ fn main() {
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
+        .setting(AppSettings::TrailingVarArg)
         .arg(
             Arg::with_name("INPUT")
-                .help("The input file to exec")
-                .default_value("")
+                .help(
+                    "The input file to exec (`-` to read from stdin), \
+                     followed by arguments passed to the program as `(command-line)`",
+                )
+                .multiple(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("disasm")
+                .long("disasm")
+                .value_name("FILE")
+                .help("Show the compiled VM code of FILE (`-` to read from stdin)"),
+        )
+        .arg(
+            Arg::with_name("eval")
+                .short("e")
+                .long("eval")
+                .value_name("EXPR")
+                .help("Evaluate EXPR and print the result"),
+        )
         .arg(
             Arg::with_name("debug")
                 .short("d")
@@ -48,14 +65,33 @@ fn main() {
         }
     };
 
-    let exec_path = value_t!(matches, "INPUT", String).unwrap();
-    let exec_flag = !exec_path.is_empty();
-    if exec_flag {
-        let exec_path = PathBuf::from(exec_path);
-        exec(&exec_path, sources);
+    if let Some(path) = matches.value_of("disasm") {
+        let input = match path {
+            "-" => Input::Stdin,
+            path => Input::File(PathBuf::from(path)),
+        };
+        disasm(input, sources);
+        return;
+    }
+
+    let mut args = values_t!(matches, "INPUT", String).unwrap_or(Vec::new());
+    let input = if let Some(expr) = matches.value_of("eval") {
+        // `-e` のときは位置引数をすべてプログラムに渡す
+        Some(Input::Expr(expr.to_string()))
+    } else if args.is_empty() {
+        None
+    } else {
+        match args.remove(0).as_str() {
+            "-" => Some(Input::Stdin),
+            path => Some(Input::File(PathBuf::from(path))),
+        }
+    };
+
+    if let Some(input) = input {
+        exec(input, sources, args);
     } else {
         println!("FZScheme in Rust (version {})\n", VERSION);
-        repl(
+        let status = repl(
             dbg_flag,
             if sources.is_empty() {
                 None
@@ -63,5 +99,6 @@ fn main() {
                 Some(sources)
             },
         );
+        std::process::exit(status);
     }
 }
@@ -128,97 +128,93 @@
 (define-macro quasiquote (lambda (x) (transfer x)))
 
 ;;; let (named-let)
-(define-macro let
-  (lambda (args . body)
-    (if (pair? args)
-        `((lambda ,(map car args) ,@body) ,@(map cadr args))
-        ;; named-let
-        `(letrec ((,args (lambda ,(map car (car body)) ,@(cdr body))))
-           (,args ,@(map cadr (car body)))))))
+(define-syntax let
+  (syntax-rules ()
+    ((_ ((name val) ...) body1 body2 ...)
+     ((lambda (name ...) body1 body2 ...) val ...))
+    ;; named-let
+    ((_ tag ((name val) ...) body1 body2 ...)
+     ((letrec ((tag (lambda (name ...) body1 body2 ...))) tag) val ...))))
 
 ;;; and
-(define-macro and
-  (lambda args
-    (if (null? args)
-        #t
-        (if (null? (cdr args))
-            (car args)
-            `(if ,(car args) (and ,@(cdr args)) #f)))))
+(define-syntax and
+  (syntax-rules ()
+    ((_) #t)
+    ((_ test) test)
+    ((_ test1 test2 ...) (if test1 (and test2 ...) #f))))
 
 ;;; or
-(define-macro or
-  (lambda args
-    (if (null? args)
-        #f
-        (if (null? (cdr args))
-            (car args)
-            `(let ((+value+ ,(car args)))
-               (if +value+ +value+ (or ,@(cdr args))))))))
+(define-syntax or
+  (syntax-rules ()
+    ((_) #f)
+    ((_ test) test)
+    ((_ test1 test2 ...)
+     (let ((x test1))
+       (if x x (or test2 ...))))))
 
 ;;; let*
-(define-macro let*
-  (lambda (args . body)
-    (if (null? (cdr args))
-        `(let (,(car args)) ,@body)
-        `(let (,(car args)) (let* ,(cdr args) ,@body)))))
+(define-syntax let*
+  (syntax-rules ()
+    ((_ (binding) body1 body2 ...)
+     (let (binding) body1 body2 ...))
+    ((_ (binding1 binding2 ...) body1 body2 ...)
+     (let (binding1) (let* (binding2 ...) body1 body2 ...)))))
 
 ;;; letrec
-(define-macro letrec
-  (lambda (args . body)
-    (let ((vars (map car args))
-          (vals (map cadr args)))
-      `(let ,(map (lambda (x) `(,x '*undef*)) vars)
-         ,@(map-2 (lambda (x y) `(set! ,x ,y)) vars vals)
-         ,@body))))
+(define-syntax letrec
+  (syntax-rules ()
+    ((_ ((var init) ...) body1 body2 ...)
+     (let ((var '*undef*) ...)
+       (set! var init) ...
+       body1 body2 ...))))
 
 ;;; begin
-(define-macro begin
-  (lambda args
-    (if (null? args)
-        `((lambda () '*undef*))
-        `((lambda () ,@args)))))
+(define-syntax begin
+  (syntax-rules ()
+    ((_) ((lambda () '*undef*)))
+    ((_ exp ...) ((lambda () exp ...)))))
 
 ;;; cond
-(define-macro cond
-  (lambda args
-    (if (null? args)
-        '*undef*
-        (if (eq? (caar args) 'else)
-            `(begin ,@(cdar args))
-            (if (null? (cdar args))
-                `(let ((+value+ ,(caar args)))
-                   (if +value+ +value+ (cond ,@(cdr args))))
-                `(if ,(caar args)
-                     (begin ,@(cdar args))
-                     (cond ,@(cdr args))))))))
+(define-syntax cond
+  (syntax-rules (else =>)
+    ((_) '*undef*)
+    ((_ (else result1 result2 ...) clause ...)
+     (begin result1 result2 ...))
+    ((_ (test => receiver) clause ...)
+     (let ((x test))
+       (if x (receiver x) (cond clause ...))))
+    ((_ (test) clause ...)
+     (let ((x test))
+       (if x x (cond clause ...))))
+    ((_ (test result1 result2 ...) clause ...)
+     (if test
+         (begin result1 result2 ...)
+         (cond clause ...)))))
 
 ;;; case
-(define-macro case
-  (lambda (key . args)
-    (if (null? args)
-        '*undef*
-        (if (eq? (caar args) 'else)
-            `(begin ,@(cdar args))
-            `(if (memv ,key ',(caar args))
-                 (begin ,@(cdar args))
-                 (case ,key ,@(cdr args)))))))
+(define-syntax case
+  (syntax-rules (else)
+    ((_ key) '*undef*)
+    ((_ key (else result1 result2 ...) clause ...)
+     (begin result1 result2 ...))
+    ((_ key ((atom ...) result1 result2 ...) clause ...)
+     (if (memv key '(atom ...))
+         (begin result1 result2 ...)
+         (case key clause ...)))))
 
 ;;; do
-(define-macro do
-  (lambda (var-form test-form . args)
-    (let ((vars (map car var-form))
-          (vals (map cadr var-form))
-          (step (map cddr var-form)))
-      `(letrec ((loop (lambda ,vars
-                        (if ,(car test-form)
-                            (begin ,@(cdr test-form))
-                            (begin
-                              ,@args
-                              (loop ,@(map-2 (lambda (x y)
-                                               (if (null? x) y (car x)))
-                                             step
-                                             vars)))))))
-         (loop ,@vals)))))
+(define-syntax do
+  (syntax-rules ()
+    ((_ ((var init step ...) ...) (test expr ...) command ...)
+     (letrec ((loop (lambda (var ...)
+                      (if test
+                          (begin expr ...)
+                          (begin
+                            command ...
+                            (loop (do "step" var step ...) ...))))))
+       (loop init ...)))
+    ((_ "step" x) x)
+    ((_ "step" x y) y)))
 
 ;;;
 ;;; マクロを使った関数の定義
@@ -0,0 +1,507 @@
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Num, One, Signed, ToPrimitive, Zero};
+
+use crate::ast::Node;
+
+/// 数値の型の階層。演算では両辺のうち上位の型に揃える
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Integer,
+    Rational,
+    Real,
+}
+
+fn level(node: &Node) -> Option<Level> {
+    match node {
+        Node::Int(_) | Node::BigInt(_) => Some(Level::Integer),
+        Node::Rational(_) => Some(Level::Rational),
+        Node::Real(_) => Some(Level::Real),
+        _ => None,
+    }
+}
+
+pub fn is_number(node: &Node) -> bool {
+    level(node).is_some()
+}
+
+pub fn is_exact(node: &Node) -> bool {
+    matches!(node, Node::Int(_) | Node::BigInt(_) | Node::Rational(_))
+}
+
+pub fn is_exact_integer(node: &Node) -> bool {
+    matches!(node, Node::Int(_) | Node::BigInt(_))
+}
+
+/// 多倍長整数を、i64 に収まるなら `Node::Int` に正規化する
+pub fn from_bigint(int: BigInt) -> Node {
+    if let Some(int) = int.to_i64() {
+        Node::Int(int)
+    } else {
+        Node::BigInt(int)
+    }
+}
+
+/// 有理数を、分母が 1 なら整数に正規化する
+pub fn from_rational(rat: BigRational) -> Node {
+    if rat.is_integer() {
+        from_bigint(rat.to_integer())
+    } else {
+        Node::Rational(rat)
+    }
+}
+
+fn to_bigint(node: &Node) -> Option<BigInt> {
+    match node {
+        Node::Int(int) => Some(BigInt::from(*int)),
+        Node::BigInt(int) => Some(int.clone()),
+        _ => None,
+    }
+}
+
+fn to_rational(node: &Node) -> Option<BigRational> {
+    match node {
+        Node::Int(_) | Node::BigInt(_) => Some(BigRational::from_integer(to_bigint(node)?)),
+        Node::Rational(rat) => Some(rat.clone()),
+        _ => None,
+    }
+}
+
+pub fn to_f64(node: &Node) -> Option<f64> {
+    match node {
+        Node::Int(int) => Some(*int as f64),
+        Node::BigInt(int) => int.to_f64(),
+        Node::Rational(rat) => rat.to_f64(),
+        Node::Real(real) => Some(*real),
+        _ => None,
+    }
+}
+
+fn arith(
+    a: &Node,
+    b: &Node,
+    int_op: fn(i64, i64) -> Option<i64>,
+    big_op: fn(BigInt, BigInt) -> BigInt,
+    rat_op: fn(BigRational, BigRational) -> BigRational,
+    real_op: fn(f64, f64) -> f64,
+) -> Option<Node> {
+    if let (Node::Int(x), Node::Int(y)) = (a, b) {
+        if let Some(result) = int_op(*x, *y) {
+            return Some(Node::Int(result));
+        }
+    }
+    match level(a)?.max(level(b)?) {
+        Level::Integer => Some(from_bigint(big_op(to_bigint(a)?, to_bigint(b)?))),
+        Level::Rational => Some(from_rational(rat_op(to_rational(a)?, to_rational(b)?))),
+        Level::Real => Some(Node::Real(real_op(to_f64(a)?, to_f64(b)?))),
+    }
+}
+
+pub fn add(a: &Node, b: &Node) -> Option<Node> {
+    arith(
+        a,
+        b,
+        i64::checked_add,
+        |x, y| x + y,
+        |x, y| x + y,
+        |x, y| x + y,
+    )
+}
+
+pub fn sub(a: &Node, b: &Node) -> Option<Node> {
+    arith(
+        a,
+        b,
+        i64::checked_sub,
+        |x, y| x - y,
+        |x, y| x - y,
+        |x, y| x - y,
+    )
+}
+
+pub fn mul(a: &Node, b: &Node) -> Option<Node> {
+    arith(
+        a,
+        b,
+        i64::checked_mul,
+        |x, y| x * y,
+        |x, y| x * y,
+        |x, y| x * y,
+    )
+}
+
+/// `/` による除算。正確数同士なら有理数になる
+pub fn div(a: &Node, b: &Node) -> Result<Node, String> {
+    if is_exact(b) && is_zero(b) && is_exact(a) {
+        return Err("division by zero".to_string());
+    }
+    let result = if level(a).max(level(b)) == Some(Level::Real) {
+        to_f64(a).zip(to_f64(b)).map(|(x, y)| Node::Real(x / y))
+    } else {
+        to_rational(a)
+            .zip(to_rational(b))
+            .map(|(x, y)| from_rational(x / y))
+    };
+    result.ok_or_else(|| "non-number object".to_string())
+}
+
+/// 整数の商（0 方向への切り捨て）
+pub fn quotient(a: &Node, b: &Node) -> Result<Node, String> {
+    integer_op(a, b, |x, y| x / y)
+}
+
+/// 整数の剰余（被除数と同じ符号）
+pub fn remainder(a: &Node, b: &Node) -> Result<Node, String> {
+    integer_op(a, b, |x, y| x % y)
+}
+
+fn integer_op(a: &Node, b: &Node, op: fn(BigInt, BigInt) -> BigInt) -> Result<Node, String> {
+    let (x, y) = match (to_bigint(a), to_bigint(b)) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return Err("non-integer object".to_string()),
+    };
+    if y.is_zero() {
+        Err("division by zero".to_string())
+    } else {
+        Ok(from_bigint(op(x, y)))
+    }
+}
+
+pub fn is_zero(node: &Node) -> bool {
+    match node {
+        Node::Int(int) => *int == 0,
+        Node::BigInt(int) => int.is_zero(),
+        Node::Rational(rat) => rat.is_zero(),
+        Node::Real(real) => *real == 0.0,
+        _ => false,
+    }
+}
+
+pub fn negate(node: &Node) -> Option<Node> {
+    sub(&Node::Int(0), node)
+}
+
+pub fn compare(a: &Node, b: &Node) -> Option<Ordering> {
+    if let (Node::Int(x), Node::Int(y)) = (a, b) {
+        return Some(x.cmp(y));
+    }
+    if level(a)?.max(level(b)?) == Level::Real {
+        to_f64(a)?.partial_cmp(&to_f64(b)?)
+    } else {
+        Some(to_rational(a)?.cmp(&to_rational(b)?))
+    }
+}
+
+pub fn to_inexact(node: &Node) -> Option<Node> {
+    to_f64(node).map(Node::Real)
+}
+
+pub fn to_exact(node: &Node) -> Result<Node, String> {
+    match node {
+        Node::Real(real) => match BigRational::from_f64(*real) {
+            Some(rat) => Ok(from_rational(rat)),
+            None => Err(format!("no exact representation: {}", node.inspect())),
+        },
+        _ if is_exact(node) => Ok(node.clone()),
+        _ => Err(format!("non-number object: {}", node.inspect())),
+    }
+}
+
+pub fn floor(node: &Node) -> Option<Node> {
+    match node {
+        Node::Int(_) | Node::BigInt(_) => Some(node.clone()),
+        Node::Rational(rat) => Some(from_bigint(rat.floor().to_integer())),
+        Node::Real(real) => Some(Node::Real(real.floor())),
+        _ => None,
+    }
+}
+
+/// 最も近い整数に丸める。ちょうど中間の場合は偶数に丸める
+pub fn round(node: &Node) -> Option<Node> {
+    match node {
+        Node::Int(_) | Node::BigInt(_) => Some(node.clone()),
+        Node::Rational(rat) => {
+            let floor = rat.floor();
+            let diff = rat - &floor;
+            let half = BigRational::new(BigInt::one(), BigInt::from(2));
+            let floor = floor.to_integer();
+            let rounded = match diff.cmp(&half) {
+                Ordering::Less => floor,
+                Ordering::Greater => floor + 1,
+                Ordering::Equal => {
+                    if floor.is_even() {
+                        floor
+                    } else {
+                        floor + 1
+                    }
+                }
+            };
+            Some(from_bigint(rounded))
+        }
+        Node::Real(real) => {
+            let rounded = real.round();
+            if (real - real.trunc()).abs() == 0.5 {
+                Some(Node::Real(2.0 * (real / 2.0).round()))
+            } else {
+                Some(Node::Real(rounded))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// 平方根。正確な数の平方根が正確に求まる場合は正確数を返す
+pub fn sqrt(node: &Node) -> Result<Node, String> {
+    if !is_number(node) {
+        return Err(format!("non-number object: {}", node.inspect()));
+    }
+    if compare(node, &Node::Int(0)) == Some(Ordering::Less) {
+        return Err(format!("negative number: {}", node.inspect()));
+    }
+    if let Some(rat) = to_rational(node) {
+        let numer = rat.numer().sqrt();
+        let denom = rat.denom().sqrt();
+        if &(&numer * &numer) == rat.numer() && &(&denom * &denom) == rat.denom() {
+            return Ok(from_rational(BigRational::new(numer, denom)));
+        }
+    }
+    Ok(Node::Real(to_f64(node).unwrap().sqrt()))
+}
+
+/// 正確数の累乗の結果として許す大きさ（ビット数）
+const MAX_EXPT_BITS: u64 = 1 << 20;
+
+/// 累乗。正確数の累乗は結果がおよそ `MAX_EXPT_BITS` ビットを超えるならエラーにする
+pub fn expt(base: &Node, exponent: &Node) -> Result<Node, String> {
+    if !is_number(base) || !is_number(exponent) {
+        return Err("non-number object".to_string());
+    }
+    if is_exact(base) && is_exact_integer(exponent) {
+        let exponent = to_bigint(exponent).unwrap();
+        let rat = to_rational(base).unwrap();
+        if rat.is_zero() && exponent.is_negative() {
+            return Err("division by zero".to_string());
+        }
+        let bits = rat.numer().bits().max(rat.denom().bits());
+        let result = if bits <= 1 {
+            // 0, 1, -1 の累乗は指数の偶奇だけで決まる
+            if exponent.is_zero() {
+                BigRational::one()
+            } else if exponent.is_even() {
+                &rat * &rat
+            } else {
+                rat
+            }
+        } else {
+            let power = exponent
+                .abs()
+                .to_u64()
+                .filter(|power| (bits - 1).saturating_mul(*power) <= MAX_EXPT_BITS)
+                .ok_or_else(|| "result is too large".to_string())?;
+            num_traits::pow(rat, power as usize)
+        };
+        if exponent.is_negative() {
+            Ok(from_rational(result.recip()))
+        } else {
+            Ok(from_rational(result))
+        }
+    } else {
+        Ok(Node::Real(
+            to_f64(base).unwrap().powf(to_f64(exponent).unwrap()),
+        ))
+    }
+}
+
+pub fn format_real(real: f64) -> String {
+    if real.is_nan() {
+        "+nan.0".to_string()
+    } else if real.is_infinite() {
+        if real > 0.0 {
+            "+inf.0".to_string()
+        } else {
+            "-inf.0".to_string()
+        }
+    } else {
+        format!("{:?}", real)
+    }
+}
+
+pub fn number_to_string(node: &Node, radix: u32) -> Option<String> {
+    match node {
+        Node::Int(_) | Node::BigInt(_) => Some(to_bigint(node)?.to_str_radix(radix)),
+        Node::Rational(rat) => Some(format!(
+            "{}/{}",
+            rat.numer().to_str_radix(radix),
+            rat.denom().to_str_radix(radix)
+        )),
+        Node::Real(real) if radix == 10 => Some(format_real(*real)),
+        _ => None,
+    }
+}
+
+/// 数値リテラルを解釈する。`#x` `#b` `#o` `#d` による基数の指定と、
+/// `#e` `#i` による正確性の指定を受け付ける
+pub fn parse_number(source: &str, default_radix: u32) -> Option<Node> {
+    let mut radix = None;
+    let mut exactness = None;
+    let mut body = source;
+    while let Some(rest) = body.strip_prefix('#') {
+        let prefix = rest.chars().next()?;
+        match prefix.to_ascii_lowercase() {
+            'x' if radix.is_none() => radix = Some(16),
+            'b' if radix.is_none() => radix = Some(2),
+            'o' if radix.is_none() => radix = Some(8),
+            'd' if radix.is_none() => radix = Some(10),
+            'e' if exactness.is_none() => exactness = Some(true),
+            'i' if exactness.is_none() => exactness = Some(false),
+            _ => return None,
+        }
+        body = &rest[prefix.len_utf8()..];
+    }
+    let node = parse_real(body, radix.unwrap_or(default_radix))?;
+    match exactness {
+        Some(true) => to_exact(&node).ok(),
+        Some(false) => to_inexact(&node),
+        None => Some(node),
+    }
+}
+
+fn parse_real(body: &str, radix: u32) -> Option<Node> {
+    if let Some((numer, denom)) = body.split_once('/') {
+        let numer = parse_integer(numer, radix)?;
+        let denom = parse_integer(denom, radix)?;
+        if denom.is_zero() || denom.is_negative() {
+            return None;
+        }
+        return Some(from_rational(BigRational::new(numer, denom)));
+    }
+    if let Some(int) = parse_integer(body, radix) {
+        return Some(from_bigint(int));
+    }
+    if radix != 10 {
+        return None;
+    }
+    match body {
+        "+inf.0" => return Some(Node::Real(f64::INFINITY)),
+        "-inf.0" => return Some(Node::Real(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Node::Real(f64::NAN)),
+        _ => (),
+    }
+    // 10 進小数: [符号] 数字列 [. 数字列] [e [符号] 数字列]
+    let unsigned = body.strip_prefix(['+', '-']).unwrap_or(body);
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(pos) => (&unsigned[..pos], Some(&unsigned[(pos + 1)..])),
+        None => (unsigned, None),
+    };
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits_ok = |s: &str| s.chars().all(|ch| ch.is_ascii_digit());
+    if int_part.is_empty() && frac_part.is_empty() || !digits_ok(int_part) || !digits_ok(frac_part)
+    {
+        return None;
+    }
+    if let Some(exponent) = exponent {
+        let exp_digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if exp_digits.is_empty() || !digits_ok(exp_digits) {
+            return None;
+        }
+    }
+    body.parse::<f64>().ok().map(Node::Real)
+}
+
+fn parse_integer(body: &str, radix: u32) -> Option<BigInt> {
+    let digits = body.strip_prefix(['+', '-']).unwrap_or(body);
+    if digits.is_empty() || !digits.chars().all(|ch| ch.is_digit(radix)) {
+        return None;
+    }
+    BigInt::from_str_radix(body.strip_prefix('+').unwrap_or(body), radix).ok()
+}
+
+#[cfg(test)]
+mod number_test {
+    use super::*;
+
+    fn rational(numer: i64, denom: i64) -> Node {
+        Node::Rational(BigRational::new(BigInt::from(numer), BigInt::from(denom)))
+    }
+
+    #[test]
+    fn parse_number_test() {
+        assert_eq!(parse_number("42", 10), Some(Node::Int(42)));
+        assert_eq!(parse_number("-3", 10), Some(Node::Int(-3)));
+        assert_eq!(parse_number("+3", 10), Some(Node::Int(3)));
+        assert_eq!(parse_number("1.5", 10), Some(Node::Real(1.5)));
+        assert_eq!(parse_number(".5", 10), Some(Node::Real(0.5)));
+        assert_eq!(parse_number("1e10", 10), Some(Node::Real(1e10)));
+        assert_eq!(parse_number("1/3", 10), Some(rational(1, 3)));
+        assert_eq!(parse_number("4/2", 10), Some(Node::Int(2)));
+        assert_eq!(parse_number("#xff", 10), Some(Node::Int(255)));
+        assert_eq!(parse_number("#b-101", 10), Some(Node::Int(-5)));
+        assert_eq!(parse_number("#o17", 10), Some(Node::Int(15)));
+        assert_eq!(parse_number("#e1.5", 10), Some(rational(3, 2)));
+        assert_eq!(parse_number("#i1/2", 10), Some(Node::Real(0.5)));
+        assert_eq!(
+            parse_number("99999999999999999999", 10),
+            Some(Node::BigInt("99999999999999999999".parse().unwrap()))
+        );
+        for source in [
+            "-", "+", "...", "1/0", "1e", "e1", "#xg", "1.2.3", ".", "#", "#é", "#x#é", "１",
+        ] {
+            assert_eq!(parse_number(source, 10), None, "source: {}", source);
+        }
+    }
+
+    #[test]
+    fn arith_test() {
+        assert_eq!(
+            add(&Node::Int(i64::MAX), &Node::Int(1)),
+            Some(Node::BigInt(BigInt::from(i64::MAX) + 1))
+        );
+        assert_eq!(
+            sub(&Node::BigInt(BigInt::from(i64::MAX) + 1), &Node::Int(1)),
+            Some(Node::Int(i64::MAX))
+        );
+        assert_eq!(add(&rational(1, 2), &rational(1, 2)), Some(Node::Int(1)));
+        assert_eq!(
+            mul(&rational(1, 2), &Node::Real(3.0)),
+            Some(Node::Real(1.5))
+        );
+        assert_eq!(div(&Node::Int(1), &Node::Int(3)), Ok(rational(1, 3)));
+        assert!(div(&Node::Int(1), &Node::Int(0)).is_err());
+        assert_eq!(
+            compare(&rational(1, 3), &Node::Real(0.3)),
+            Some(Ordering::Greater)
+        );
+    }
+
+    #[test]
+    fn round_test() {
+        assert_eq!(round(&rational(5, 2)), Some(Node::Int(2)));
+        assert_eq!(round(&rational(7, 2)), Some(Node::Int(4)));
+        assert_eq!(round(&Node::Real(2.5)), Some(Node::Real(2.0)));
+        assert_eq!(round(&Node::Real(-3.5)), Some(Node::Real(-4.0)));
+        assert_eq!(floor(&rational(-1, 2)), Some(Node::Int(-1)));
+    }
+
+    #[test]
+    fn sqrt_expt_test() {
+        assert_eq!(sqrt(&Node::Int(16)), Ok(Node::Int(4)));
+        assert_eq!(sqrt(&rational(1, 4)), Ok(rational(1, 2)));
+        assert_eq!(sqrt(&Node::Int(2)), Ok(Node::Real(2f64.sqrt())));
+        assert_eq!(expt(&Node::Int(2), &Node::Int(-2)), Ok(rational(1, 4)));
+        assert_eq!(
+            expt(&Node::Int(2), &Node::Int(100)),
+            Ok(Node::BigInt(BigInt::from(1) << 100))
+        );
+        assert_eq!(expt(&Node::Int(4), &Node::Real(0.5)), Ok(Node::Real(2.0)));
+
+        // 結果が大きすぎる正確数の累乗はエラーにする
+        let huge = Node::Int(100_000_000);
+        assert!(expt(&Node::Int(2), &huge).is_err());
+        assert!(expt(&rational(1, 3), &huge).is_err());
+        assert_eq!(expt(&Node::Int(-1), &huge), Ok(Node::Int(1)));
+        assert_eq!(expt(&Node::Int(0), &huge), Ok(Node::Int(0)));
+    }
+}
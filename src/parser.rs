@@ -1,137 +1,148 @@
 use std::iter::Peekable;
 
-use crate::{ast::Node, lexer::Lexer, token::Token};
+use crate::{
+    ast::Node,
+    error::ParseError,
+    lexer::{Lexer, Spanned},
+    span::{Located, Span, SpanTable},
+    token::Token,
+};
+
+pub type ParseResult<T> = Result<T, Located<ParseError>>;
 
 #[derive(Debug)]
 pub struct Parser<'a> {
-    lex: Peekable<Lexer<'a>>,
+    lex: Peekable<Spanned<'a>>,
+    spans: SpanTable,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(lex: Lexer<'a>) -> Self {
         Parser {
-            lex: lex.peekable(),
+            lex: lex.spanned().peekable(),
+            spans: SpanTable::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Node>, &'static str> {
+    pub fn parse(&mut self) -> ParseResult<Vec<Node>> {
         let mut nodes = Vec::new();
 
         while self.lex.peek().is_some() {
-            match self.parse_expr() {
-                Ok(expr) => nodes.push(expr),
-                Err(msg) => return Err(msg),
-            }
+            nodes.push(self.parse_expr()?);
         }
 
         Ok(nodes)
     }
 
-    fn parse_expr(&mut self) -> Result<Node, &'static str> {
-        if let Some(tok) = self.lex.next() {
+    /// 構文解析で作ったリストの位置の対応表を取り出す
+    pub fn into_spans(self) -> SpanTable {
+        self.spans
+    }
+
+    /// 位置つきのリストを作る
+    fn located_list(&mut self, items: Vec<Node>, tail: Node, span: Span) -> Node {
+        let node = Node::list_with_tail(items, tail);
+        self.spans.insert(&node, span);
+        node
+    }
+
+    fn parse_expr(&mut self) -> ParseResult<Node> {
+        if let Some((tok, span)) = self.lex.next() {
+            let err = |error| Err(Located::new(error, Some(span)));
             match tok {
                 Token::True => Ok(Node::Bool(true)),
                 Token::False => Ok(Node::Bool(false)),
                 Token::Integer(int) => Ok(Node::Int(int)),
+                Token::BigInt(int) => Ok(Node::BigInt(int)),
+                Token::Rational(rat) => Ok(Node::Rational(rat)),
+                Token::Real(real) => Ok(Node::Real(real)),
                 Token::Str(string) => Ok(Node::Str(string)),
-                Token::Ident(ident) => Ok(Node::Ident(ident)),
-                Token::Quote => Ok(Node::List(vec![
-                    Node::Ident("quote".to_string()),
-                    self.parse_expr()?,
-                    Node::nil(),
-                ])),
+                Token::Char(ch) => Ok(Node::Char(ch)),
+                Token::Ident(ident) => Ok(Node::ident(&ident)),
+                Token::Quote => self.parse_abbrev("quote", span),
                 Token::Quasiquote => {
-                    if let Some(next_tok) = self.lex.peek() {
+                    if let Some((next_tok, _)) = self.lex.peek() {
                         if matches!(next_tok, Token::Lparen | Token::Lbracket) {
-                            Ok(Node::List(vec![
-                                Node::Ident("quasiquote".to_string()),
-                                self.parse_expr()?,
-                                Node::nil(),
-                            ]))
+                            self.parse_abbrev("quasiquote", span)
                         } else {
-                            Err("quasiquote must exists before a list.")
+                            err(ParseError::QuasiquoteNotList)
                         }
                     } else {
-                        Err("missing expr next to the quasiquote")
+                        err(ParseError::MissingDatum("quasiquote"))
                     }
                 }
-                Token::Unquote => Ok(Node::List(vec![
-                    Node::Ident("unquote".to_string()),
-                    self.parse_expr()?,
-                    Node::nil(),
-                ])),
-                Token::UnquoteSplicing => Ok(Node::List(vec![
-                    Node::Ident("unquote-splicing".to_string()),
-                    self.parse_expr()?,
-                    Node::nil(),
-                ])),
-                Token::Lparen | Token::Rparen => self.parse_list(),
-                _ => Err("parsing expr failed."),
+                Token::Unquote => self.parse_abbrev("unquote", span),
+                Token::UnquoteSplicing => self.parse_abbrev("unquote-splicing", span),
+                Token::Lparen | Token::Lbracket => self.parse_list(span),
+                Token::Rparen => err(ParseError::UnexpectedToken(")".to_string())),
+                Token::Rbracket => err(ParseError::UnexpectedToken("]".to_string())),
+                Token::Dot => err(ParseError::UnexpectedToken(".".to_string())),
+                Token::Illegal(lex_err) => err(ParseError::Lex(lex_err)),
+                Token::Eof => err(ParseError::UnexpectedToken("EOF".to_string())),
             }
         } else {
             unreachable!("don't call parse_expr when the next token don't exists.");
         }
     }
 
-    fn parse_list(&mut self) -> Result<Node, &'static str> {
+    /// `'x` などの省略記法を `(quote x)` などのリストにする
+    fn parse_abbrev(&mut self, name: &'static str, span: Span) -> ParseResult<Node> {
+        if self.lex.peek().is_none() {
+            return Err(Located::new(ParseError::MissingDatum(name), Some(span)));
+        }
+        let expr = self.parse_expr()?;
+        Ok(self.located_list(vec![Node::ident(name), expr], Node::nil(), span))
+    }
+
+    fn parse_list(&mut self, start: Span) -> ParseResult<Node> {
         let mut nodes = Vec::new();
 
         loop {
-            if let Some(tok) = self.lex.peek() {
+            if let Some((tok, span)) = self.lex.peek() {
+                let span = *span;
                 match tok {
-                    Token::True
-                    | Token::False
-                    | Token::Integer(_)
-                    | Token::Str(_)
-                    | Token::Ident(_)
-                    | Token::Quote
-                    | Token::Quasiquote
-                    | Token::Unquote
-                    | Token::UnquoteSplicing
-                    | Token::Lparen
-                    | Token::Lbracket => {
-                        if let Ok(node) = self.parse_expr() {
-                            nodes.push(node);
-                        } else {
-                            return Err("parsing list failed.");
-                        }
-                    }
                     Token::Rparen | Token::Rbracket => {
                         self.lex.next();
                         break;
                     }
                     Token::Dot => {
                         self.lex.next();
-                        if let Ok(node) = self.parse_expr() {
-                            nodes.push(node);
-                            assert!(matches!(
-                                self.lex.peek().unwrap(),
-                                Token::Rparen | Token::Rbracket
-                            ));
-                            self.lex.next();
-                            return Ok(Node::List(nodes));
-                        } else {
-                            return Err("parsing list failed.");
+                        if nodes.is_empty() {
+                            return Err(Located::new(ParseError::BadDottedList, Some(span)));
                         }
+                        if self.lex.peek().is_none() {
+                            return Err(Located::new(ParseError::Unclosed, Some(start)));
+                        }
+                        let tail = self.parse_expr()?;
+                        return match self.lex.next() {
+                            Some((Token::Rparen | Token::Rbracket, _)) => {
+                                Ok(self.located_list(nodes, tail, start))
+                            }
+                            Some((_, span)) => {
+                                Err(Located::new(ParseError::BadDottedList, Some(span)))
+                            }
+                            None => Err(Located::new(ParseError::Unclosed, Some(start))),
+                        };
                     }
-                    _ => return Err("parsing list failed."),
+                    _ => nodes.push(self.parse_expr()?),
                 };
             } else {
-                return Err("parsing list failed: list is not closed.");
+                return Err(Located::new(ParseError::Unclosed, Some(start)));
             }
         }
-        if !nodes.is_empty() {
-            nodes.push(Node::nil());
-        }
-
-        Ok(Node::List(nodes))
+        Ok(self.located_list(nodes, Node::nil(), start))
     }
 }
 
 #[cfg(test)]
 mod parser_test {
     use super::Parser;
-    use crate::{ast::Node, lexer::Lexer};
+    use crate::{
+        ast::Node,
+        error::{LexError, ParseError},
+        lexer::Lexer,
+        span::Span,
+    };
 
     #[test]
     fn parse_test() {
@@ -142,33 +153,24 @@ mod parser_test {
 "#;
 
         let expected = vec![
-            Node::List(vec![
-                Node::Ident("define".to_string()),
-                Node::List(vec![
-                    Node::Ident("rect-area".to_string()),
-                    Node::Ident("w".to_string()),
-                    Node::Ident("h".to_string()),
-                    Node::nil(),
-                ]),
-                Node::List(vec![
-                    Node::Ident("*".to_string()),
-                    Node::Ident("w".to_string()),
-                    Node::Ident("h".to_string()),
-                    Node::nil(),
+            Node::list(vec![
+                Node::ident("define"),
+                Node::list(vec![
+                    Node::ident("rect-area"),
+                    Node::ident("w"),
+                    Node::ident("h"),
                 ]),
-                Node::nil(),
+                Node::list(vec![Node::ident("*"), Node::ident("w"), Node::ident("h")]),
             ]),
-            Node::List(vec![
-                Node::Ident("display".to_string()),
-                Node::List(vec![
-                    Node::Ident("rect-area".to_string()),
+            Node::list(vec![
+                Node::ident("display"),
+                Node::list(vec![
+                    Node::ident("rect-area"),
                     Node::Int(128),
                     Node::Int(256),
-                    Node::nil(),
                 ]),
-                Node::nil(),
             ]),
-            Node::List(vec![Node::Ident("newline".to_string()), Node::nil()]),
+            Node::list(vec![Node::ident("newline")]),
         ];
 
         let lex = Lexer::new(source);
@@ -185,20 +187,11 @@ mod parser_test {
     #[test]
     fn parse_quote_test() {
         let source = "(if #t 'a 'b)";
-        let expected = vec![Node::List(vec![
-            Node::Ident("if".to_string()),
+        let expected = vec![Node::list(vec![
+            Node::ident("if"),
             Node::Bool(true),
-            Node::List(vec![
-                Node::Ident("quote".to_string()),
-                Node::Ident("a".to_string()),
-                Node::nil(),
-            ]),
-            Node::List(vec![
-                Node::Ident("quote".to_string()),
-                Node::Ident("b".to_string()),
-                Node::nil(),
-            ]),
-            Node::nil(),
+            Node::list(vec![Node::ident("quote"), Node::ident("a")]),
+            Node::list(vec![Node::ident("quote"), Node::ident("b")]),
         ])];
 
         let lex = Lexer::new(source);
@@ -211,4 +204,72 @@ mod parser_test {
             expected, result
         );
     }
+
+    #[test]
+    fn parse_span_test() {
+        let source = "(define (f x)\n  (g 'x))";
+        let mut p = Parser::new(Lexer::new(source));
+        let node = p.parse().unwrap().remove(0);
+        let spans = p.into_spans();
+
+        let params = node.nth_cdr(1).unwrap().car().unwrap();
+        let body = node.nth_cdr(2).unwrap().car().unwrap();
+        let quoted = body.nth_cdr(1).unwrap().car().unwrap();
+        assert_eq!(spans.get(&node), Some(Span::new(1, 1)));
+        assert_eq!(spans.get(&params), Some(Span::new(1, 9)));
+        assert_eq!(spans.get(&body), Some(Span::new(2, 3)));
+        assert_eq!(spans.get(&quoted), Some(Span::new(2, 6)));
+        assert_eq!(spans.get(&Node::list(vec![Node::Int(1)])), None);
+    }
+
+    #[test]
+    fn parse_error_span_test() {
+        let source = "(a\n  (b c)";
+        let err = Parser::new(Lexer::new(source)).parse().unwrap_err();
+        assert_eq!(err.span, Some(Span::new(1, 1)));
+
+        let source = "(a\n  #z)";
+        let err = Parser::new(Lexer::new(source)).parse().unwrap_err();
+        assert_eq!(err.span, Some(Span::new(2, 3)));
+    }
+
+    #[test]
+    fn parse_error_test() {
+        let cases = [
+            ("(a . b c)", ParseError::BadDottedList),
+            ("( . a)", ParseError::BadDottedList),
+            ("(a . b", ParseError::Unclosed),
+            (")", ParseError::UnexpectedToken(")".to_string())),
+            ("'", ParseError::MissingDatum("quote")),
+            ("`a", ParseError::QuasiquoteNotList),
+            ("\"abc", ParseError::Lex(LexError::UnterminatedString)),
+        ];
+        for (source, expected) in cases {
+            let err = Parser::new(Lexer::new(source)).parse().unwrap_err();
+            assert_eq!(err.error, expected, "source: {}", source);
+        }
+    }
+
+    #[test]
+    fn incomplete_test() {
+        let incomplete = [
+            "(define (f x)",
+            "(a . ",
+            "'",
+            "(f ,",
+            "\"abc",
+            "`",
+            "(a\n  (b c)\n",
+        ];
+        for source in incomplete {
+            let err = Parser::new(Lexer::new(source)).parse().unwrap_err();
+            assert!(err.error.is_incomplete(), "source: {}", source);
+        }
+
+        let malformed = ["(a))", "(a . b c)", "`a", "(#z", "( . a"];
+        for source in malformed {
+            let err = Parser::new(Lexer::new(source)).parse().unwrap_err();
+            assert!(!err.error.is_incomplete(), "source: {}", source);
+        }
+    }
 }
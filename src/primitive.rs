@@ -1,395 +1,597 @@
 use std::io::{self, Write};
 
-use crate::ast::Node;
+use std::cmp::Ordering;
 
-pub fn prim_car(args: Vec<Node>) -> Node {
-    if let Node::List(items) = args[0].clone() {
-        items[0].clone()
-    } else {
-        Node::Error(format!("car: argument is not pair: {}", args[0].inspect()))
+use crate::{
+    ast::Node,
+    convert::convert_arg,
+    error::{RuntimeError, TypeError},
+    heap, number,
+    symbol::Symbol,
+};
+
+/// `position` 番目（1 始まり）の引数の型が合わない
+fn wrong_type(name: &str, position: usize, expected: &str, arg: &Node) -> RuntimeError {
+    RuntimeError::WrongType {
+        name: name.to_string(),
+        position,
+        error: TypeError::new(expected, arg),
     }
 }
 
-pub fn prim_cdr(args: Vec<Node>) -> Node {
-    if let Node::List(items) = args[0].clone() {
-        let items = items[1..].to_vec();
-        if items.len() == 1 {
-            items[0].clone()
-        } else {
-            Node::List(items)
-        }
-    } else {
-        Node::Error(format!("cdr: argument is not pair: {}", args[0].inspect()))
-    }
+pub fn prim_car(args: &[Node]) -> Result<Node, RuntimeError> {
+    args[0]
+        .car()
+        .ok_or_else(|| wrong_type("car", 1, "pair", &args[0]))
 }
 
-pub fn prim_cons(args: Vec<Node>) -> Node {
-    let fst = args[0].clone();
-    let snd = args[1].clone();
-    let mut v = vec![fst];
-    if snd.is_pair() {
-        if let Node::List(nodes) = snd {
-            for node in nodes {
-                v.push(node.clone());
-            }
-        } else {
-            unreachable!("snd is pair, so it is also Node::List.");
-        }
-    } else {
-        v.push(snd);
-    }
-    Node::List(v)
+pub fn prim_cdr(args: &[Node]) -> Result<Node, RuntimeError> {
+    args[0]
+        .cdr()
+        .ok_or_else(|| wrong_type("cdr", 1, "pair", &args[0]))
 }
 
-pub fn prim_eq(args: Vec<Node>) -> Node {
-    let fst = args[0].clone();
-    let snd = args[1].clone();
-    Node::Bool(fst == snd)
+pub fn prim_cons(args: &[Node]) -> Result<Node, RuntimeError> {
+    Ok(Node::cons(args[0].clone(), args[1].clone()))
 }
 
-pub fn prim_eqv(args: Vec<Node>) -> Node {
+pub fn prim_set_car(args: &[Node]) -> Result<Node, RuntimeError> {
+    args[0]
+        .set_car(args[1].clone())
+        .map(|_| Node::Undef)
+        .ok_or_else(|| wrong_type("set-car!", 1, "pair", &args[0]))
+}
+
+pub fn prim_set_cdr(args: &[Node]) -> Result<Node, RuntimeError> {
+    args[0]
+        .set_cdr(args[1].clone())
+        .map(|_| Node::Undef)
+        .ok_or_else(|| wrong_type("set-cdr!", 1, "pair", &args[0]))
+}
+
+pub fn prim_eq(args: &[Node]) -> Result<Node, RuntimeError> {
+    Ok(Node::Bool(args[0].is_eq(&args[1])))
+}
+
+pub fn prim_eqv(args: &[Node]) -> Result<Node, RuntimeError> {
     prim_eq(args)
 }
 
-pub fn prim_pair(args: Vec<Node>) -> Node {
-    Node::Bool(args[0].is_pair())
+pub fn prim_pair(args: &[Node]) -> Result<Node, RuntimeError> {
+    Ok(Node::Bool(args[0].is_pair()))
 }
 
-pub fn prim_display(args: Vec<Node>) -> Node {
-    let content = if let Node::Str(string) = args[0].clone() {
-        string
-    } else {
-        args[0].inspect()
+/// 実行を打ち切り、処理系の呼び出し元に終了ステータスを伝える。
+/// 引数がなければ 0、`#t` なら 0、`#f` なら 1 とする
+pub fn prim_exit(args: &[Node]) -> Result<Node, RuntimeError> {
+    let status = match args.first() {
+        None | Some(Node::Bool(true)) => 0,
+        Some(Node::Bool(false)) => 1,
+        Some(Node::Int(int)) if i32::try_from(*int).is_ok() => *int as i32,
+        Some(other) => {
+            return Err(RuntimeError::Primitive(format!(
+                "exit: invalid exit status: {}",
+                other.inspect()
+            )))
+        }
     };
-    print!("{}", content);
+    Err(RuntimeError::Exit(status))
+}
+
+pub fn prim_display(args: &[Node]) -> Result<Node, RuntimeError> {
+    match &args[0] {
+        Node::Str(string) => print!("{}", string),
+        Node::Char(ch) => print!("{}", ch),
+        other => print!("{}", other.inspect()),
+    }
+    io::stdout().flush().unwrap();
+    Ok(Node::Undef)
+}
+
+/// 読み戻せる表記で出力する
+pub fn prim_write(args: &[Node]) -> Result<Node, RuntimeError> {
+    print!("{}", args[0].inspect());
     io::stdout().flush().unwrap();
-    Node::Undef
+    Ok(Node::Undef)
 }
 
-pub fn prim_newline(_: Vec<Node>) -> Node {
+pub fn prim_newline(_: &[Node]) -> Result<Node, RuntimeError> {
     println!();
-    Node::Undef
-}
-
-pub fn prim_plus(args: Vec<Node>) -> Node {
-    let mut result = 0;
-    let mut args = args;
-    // 末尾の nil を削除
-    args.pop();
-    for arg in args {
-        match arg {
-            Node::Int(num) => result += num,
-            Node::Error(_) => return arg,
-            _ => {
-                return Node::Error(format!(
-                    "cannot apply `+` for non-integer object: {}",
-                    arg.inspect()
-                ))
-            }
-        }
+    Ok(Node::Undef)
+}
+
+/// 循環参照で残っているフレームを回収し、回収した数を返す
+pub fn prim_gc(_: &[Node]) -> Result<Node, RuntimeError> {
+    Ok(Node::Int(heap::collect() as i64))
+}
+
+/// ヒープの統計情報を `((frames . n) ...)` の形の連想リストで返す
+pub fn prim_heap_stats(_: &[Node]) -> Result<Node, RuntimeError> {
+    let stats = heap::stats();
+    let entry = |name: &str, value: usize| Node::cons(Node::ident(name), Node::Int(value as i64));
+    Ok(Node::list(vec![
+        entry("frames", stats.frames),
+        entry("allocated", stats.allocated),
+        entry("collections", stats.collections),
+        entry("freed", stats.freed),
+    ]))
+}
+
+fn non_number_error(name: &str, position: usize, arg: &Node) -> RuntimeError {
+    wrong_type(name, position, "number", arg)
+}
+
+/// 型以外の理由で計算できなかったことを、手続きの名前を添えて報告する
+fn numeric_error(name: &str, msg: String) -> RuntimeError {
+    RuntimeError::Primitive(format!("{}: {}", name, msg))
+}
+
+/// 引数が全て数であることを確かめる
+fn check_numbers(name: &str, args: &[Node]) -> Result<(), RuntimeError> {
+    match args.iter().position(|arg| !number::is_number(arg)) {
+        Some(i) => Err(non_number_error(name, i + 1, &args[i])),
+        None => Ok(()),
     }
-    Node::Int(result)
-}
-
-pub fn prim_times(args: Vec<Node>) -> Node {
-    let mut result = 1;
-    let mut args = args;
-    // 末尾の nil を削除
-    args.pop();
-    for arg in args {
-        match arg {
-            Node::Int(num) => result *= num,
-            Node::Error(_) => return arg,
-            _ => {
-                return Node::Error(format!(
-                    "cannot apply `*` for non-integer object: {}",
-                    arg.inspect()
-                ))
-            }
-        }
+}
+
+/// `args` の先頭が `first` 番目の引数であるとして、左から順に畳み込む
+fn fold_numbers(
+    name: &str,
+    init: Node,
+    args: &[Node],
+    first: usize,
+    op: fn(&Node, &Node) -> Option<Node>,
+) -> Result<Node, RuntimeError> {
+    let mut result = init;
+    for (i, arg) in args.iter().enumerate() {
+        result = op(&result, arg).ok_or_else(|| non_number_error(name, first + i, arg))?;
     }
-    Node::Int(result)
+    Ok(result)
+}
+
+pub fn prim_plus(args: &[Node]) -> Result<Node, RuntimeError> {
+    fold_numbers("+", Node::Int(0), args, 1, number::add)
+}
+
+pub fn prim_times(args: &[Node]) -> Result<Node, RuntimeError> {
+    fold_numbers("*", Node::Int(1), args, 1, number::mul)
 }
 
-pub fn prim_minus(args: Vec<Node>) -> Node {
-    let mut args = args;
-    // 末尾の nil を削除
-    args.pop();
-    if args.is_empty() {
-        return Node::Error("`-`: arguments is empty".to_string());
+pub fn prim_minus(args: &[Node]) -> Result<Node, RuntimeError> {
+    let fst = &args[0];
+    if !number::is_number(fst) {
+        return Err(non_number_error("-", 1, fst));
     }
-    if let Node::Int(num) = args[0].clone() {
-        if args.len() == 1 {
-            Node::Int(-num)
-        } else {
-            let mut result = num;
-            args.remove(0);
-            for arg in args {
-                match arg {
-                    Node::Int(num) => result -= num,
-                    Node::Error(_) => return arg,
-                    _ => {
-                        return Node::Error(format!(
-                            "cannot apply `-` for non-integer object: {}",
-                            arg.inspect()
-                        ))
-                    }
-                }
-            }
-            Node::Int(result)
-        }
+    if args.len() == 1 {
+        Ok(number::negate(fst).unwrap())
     } else {
-        Node::Error(format!(
-            "cannot apply `-` for non-integer object: {}",
-            args[0].inspect()
-        ))
+        fold_numbers("-", fst.clone(), &args[1..], 2, number::sub)
     }
 }
 
-pub fn prim_div(args: Vec<Node>) -> Node {
-    if args.len() < 3 {
-        return Node::Error(format!(
-            "div: shortage of the numbers of arguments {}",
-            args.len() - 1
-        ));
-    }
-    let fst = args[0].clone();
-    let snd = args[1].clone();
-    if let Node::Int(fst_num) = fst {
-        if let Node::Int(snd_num) = snd {
-            Node::Int(fst_num / snd_num)
-        } else {
-            Node::Error(format!(
-                "`div`: second argument is not integer: {}",
-                args[1].inspect()
-            ))
-        }
+pub fn prim_slash(args: &[Node]) -> Result<Node, RuntimeError> {
+    // 引数が一つであれば、その逆数を求める
+    let (mut result, rest, first) = if args.len() == 1 {
+        (Node::Int(1), args, 1)
+    } else if number::is_number(&args[0]) {
+        (args[0].clone(), &args[1..], 2)
     } else {
-        Node::Error(format!(
-            "`div`: first argument is not integer: {}",
-            args[0].inspect()
-        ))
+        return Err(non_number_error("/", 1, &args[0]));
+    };
+    for (i, arg) in rest.iter().enumerate() {
+        if !number::is_number(arg) {
+            return Err(non_number_error("/", first + i, arg));
+        }
+        result = number::div(&result, arg).map_err(|msg| numeric_error("/", msg))?;
     }
+    Ok(result)
 }
 
-pub fn prim_modulo(args: Vec<Node>) -> Node {
-    if args.len() < 3 {
-        return Node::Error(format!(
-            "modulo: shortage of the numbers of arguments {}",
-            args.len() - 1
-        ));
+fn integer_division(
+    name: &str,
+    args: &[Node],
+    op: fn(&Node, &Node) -> Result<Node, String>,
+) -> Result<Node, RuntimeError> {
+    if let Some(i) = args.iter().position(|arg| !number::is_exact_integer(arg)) {
+        return Err(wrong_type(name, i + 1, "exact integer", &args[i]));
     }
-    let fst = args[0].clone();
-    let snd = args[1].clone();
-    if let Node::Int(fst_num) = fst {
-        if let Node::Int(snd_num) = snd {
-            Node::Int(fst_num % snd_num)
-        } else {
-            Node::Error(format!(
-                "`modulo`: second argument is not integer: {}",
-                args[1].inspect()
-            ))
-        }
-    } else {
-        Node::Error(format!(
-            "`modulo`: first argument is not integer: {}",
-            args[0].inspect()
-        ))
+    op(&args[0], &args[1]).map_err(|msg| {
+        numeric_error(
+            name,
+            format!("{}: {} {}", msg, args[0].inspect(), args[1].inspect()),
+        )
+    })
+}
+
+pub fn prim_div(args: &[Node]) -> Result<Node, RuntimeError> {
+    integer_division("div", args, number::quotient)
+}
+
+pub fn prim_modulo(args: &[Node]) -> Result<Node, RuntimeError> {
+    integer_division("modulo", args, number::remainder)
+}
+
+fn compare_numbers(
+    name: &str,
+    args: &[Node],
+    pred: fn(Ordering) -> bool,
+) -> Result<Node, RuntimeError> {
+    check_numbers(name, args)?;
+    let result = args
+        .windows(2)
+        .all(|pair| number::compare(&pair[0], &pair[1]).is_some_and(pred));
+    Ok(Node::Bool(result))
+}
+
+pub fn prim_ope_equal(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_numbers("=", args, Ordering::is_eq)
+}
+
+pub fn prim_lt(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_numbers("<", args, Ordering::is_lt)
+}
+
+pub fn prim_gt(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_numbers(">", args, Ordering::is_gt)
+}
+
+pub fn prim_le(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_numbers("<=", args, Ordering::is_le)
+}
+
+pub fn prim_ge(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_numbers(">=", args, Ordering::is_ge)
+}
+
+fn to_inexact(name: &str, args: &[Node]) -> Result<Node, RuntimeError> {
+    number::to_inexact(&args[0]).ok_or_else(|| non_number_error(name, 1, &args[0]))
+}
+
+pub fn prim_exact_to_inexact(args: &[Node]) -> Result<Node, RuntimeError> {
+    to_inexact("exact->inexact", args)
+}
+
+pub fn prim_inexact(args: &[Node]) -> Result<Node, RuntimeError> {
+    to_inexact("inexact", args)
+}
+
+pub fn prim_inexact_to_exact(args: &[Node]) -> Result<Node, RuntimeError> {
+    check_numbers("exact", args)?;
+    number::to_exact(&args[0]).map_err(|msg| numeric_error("exact", msg))
+}
+
+pub fn prim_floor(args: &[Node]) -> Result<Node, RuntimeError> {
+    number::floor(&args[0]).ok_or_else(|| non_number_error("floor", 1, &args[0]))
+}
+
+pub fn prim_round(args: &[Node]) -> Result<Node, RuntimeError> {
+    number::round(&args[0]).ok_or_else(|| non_number_error("round", 1, &args[0]))
+}
+
+pub fn prim_sqrt(args: &[Node]) -> Result<Node, RuntimeError> {
+    check_numbers("sqrt", args)?;
+    number::sqrt(&args[0]).map_err(|msg| numeric_error("sqrt", msg))
+}
+
+pub fn prim_expt(args: &[Node]) -> Result<Node, RuntimeError> {
+    check_numbers("expt", args)?;
+    number::expt(&args[0], &args[1]).map_err(|msg| numeric_error("expt", msg))
+}
+
+/// 省略可能な第二引数の基数を取り出す
+fn radix_arg(name: &str, args: &[Node]) -> Result<u32, RuntimeError> {
+    match args.get(1) {
+        None => Ok(10),
+        Some(Node::Int(radix)) if matches!(radix, 2 | 8 | 10 | 16) => Ok(*radix as u32),
+        Some(arg @ Node::Int(_)) => Err(RuntimeError::OutOfRange {
+            name: name.to_string(),
+            position: 2,
+            given: arg.inspect(),
+        }),
+        Some(arg) => Err(wrong_type(name, 2, "integer", arg)),
     }
 }
 
-pub fn prim_ope_equal(args: Vec<Node>) -> Node {
-    let mut args = args;
-    // 末尾の nil を削除
-    args.pop();
-    if args.len() < 2 {
-        return Node::Error(format!(
-            "`=`: shortage of the numbers of arguments {}",
-            args.len()
-        ));
+pub fn prim_number_to_string(args: &[Node]) -> Result<Node, RuntimeError> {
+    let radix = radix_arg("number->string", args)?;
+    match number::number_to_string(&args[0], radix) {
+        Some(string) => Ok(Node::Str(string)),
+        None if number::is_number(&args[0]) => Err(numeric_error(
+            "number->string",
+            format!("cannot print inexact number in radix {}", radix),
+        )),
+        None => Err(non_number_error("number->string", 1, &args[0])),
     }
-    let fst = args.remove(0);
-    for arg in args {
-        match arg {
-            Node::Int(_) => {
-                if fst == arg {
-                    continue;
-                } else {
-                    return Node::Bool(false);
-                }
-            }
-            Node::Error(_) => return arg,
-            _ => {
-                return Node::Error(format!(
-                    "cannot apply `=` for non-integer object: {}",
-                    arg.inspect()
-                ))
-            }
-        }
+}
+
+pub fn prim_string_to_number(args: &[Node]) -> Result<Node, RuntimeError> {
+    let radix = radix_arg("string->number", args)?;
+    let string = string_arg("string->number", 1, &args[0])?;
+    Ok(number::parse_number(string, radix).unwrap_or(Node::Bool(false)))
+}
+
+pub fn prim_number(args: &[Node]) -> Result<Node, RuntimeError> {
+    Ok(Node::Bool(number::is_number(&args[0])))
+}
+
+pub fn prim_symbol(args: &[Node]) -> Result<Node, RuntimeError> {
+    Ok(Node::Bool(matches!(args[0], Node::Ident(_))))
+}
+
+pub fn prim_string_to_symbol(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string->symbol", 1, &args[0])?;
+    Ok(Node::ident(string))
+}
+
+pub fn prim_symbol_to_string(args: &[Node]) -> Result<Node, RuntimeError> {
+    match &args[0] {
+        Node::Ident(sym) => Ok(Node::Str(sym.to_string())),
+        other => Err(wrong_type("symbol->string", 1, "symbol", other)),
     }
-    Node::Bool(true)
-}
-
-pub fn prim_lt(args: Vec<Node>) -> Node {
-    let mut args = args;
-    // 末尾の nil を削除
-    args.pop();
-    if args.len() < 2 {
-        return Node::Error(format!(
-            "`<`: shortage of the numbers of arguments {}",
-            args.len()
-        ));
+}
+
+/// 他のどのシンボルとも `eq?` にならないシンボルを作る。名前の接頭辞を指定できる
+pub fn prim_gensym(args: &[Node]) -> Result<Node, RuntimeError> {
+    let prefix = match args.first() {
+        None => "g".to_string(),
+        Some(Node::Str(prefix)) => prefix.clone(),
+        Some(Node::Ident(prefix)) => prefix.to_string(),
+        Some(other) => return Err(wrong_type("gensym", 1, "string or symbol", other)),
+    };
+    Ok(Node::Ident(Symbol::gensym(&prefix)))
+}
+
+pub fn prim_char(args: &[Node]) -> Result<Node, RuntimeError> {
+    Ok(Node::Bool(matches!(args[0], Node::Char(_))))
+}
+
+pub fn prim_char_to_integer(args: &[Node]) -> Result<Node, RuntimeError> {
+    let ch: char = convert_arg("char->integer", 1, &args[0])?;
+    Ok(Node::Int(ch as i64))
+}
+
+/// Unicode のスカラー値でない整数は範囲外とする
+pub fn prim_integer_to_char(args: &[Node]) -> Result<Node, RuntimeError> {
+    let code: i64 = convert_arg("integer->char", 1, &args[0])?;
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(ch) => Ok(Node::Char(ch)),
+        None => Err(RuntimeError::OutOfRange {
+            name: "integer->char".to_string(),
+            position: 1,
+            given: args[0].inspect(),
+        }),
     }
-    if let Node::Int(fst_num) = args[0].clone() {
-        args.remove(0);
-        let mut prev_num = fst_num;
-        for arg in args {
-            match arg {
-                Node::Int(num) => {
-                    if prev_num < num {
-                        prev_num = num;
-                        continue;
-                    } else {
-                        return Node::Bool(false);
-                    }
-                }
-                Node::Error(_) => return arg,
-                _ => {
-                    return Node::Error(format!(
-                        "cannot apply `<` for non-integer object: {}",
-                        arg.inspect()
-                    ))
-                }
-            }
-        }
-        Node::Bool(true)
+}
+
+fn compare_chars(
+    name: &str,
+    args: &[Node],
+    pred: fn(char, char) -> bool,
+) -> Result<Node, RuntimeError> {
+    let chars = args
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| convert_arg(name, i + 1, arg))
+        .collect::<Result<Vec<char>, _>>()?;
+    Ok(Node::Bool(
+        chars.windows(2).all(|pair| pred(pair[0], pair[1])),
+    ))
+}
+
+pub fn prim_char_equal(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_chars("char=?", args, |a, b| a == b)
+}
+
+pub fn prim_char_lt(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_chars("char<?", args, |a, b| a < b)
+}
+
+// 大文字・小文字への変換が一文字にならない場合は元の文字のままにする
+fn convert_case<I: ExactSizeIterator<Item = char>>(ch: char, mut converted: I) -> char {
+    if converted.len() == 1 {
+        converted.next().unwrap()
     } else {
-        Node::Error(format!(
-            "cannot apply `<` for non-integer object: {}",
-            args[0].inspect()
-        ))
+        ch
     }
 }
 
-pub fn prim_gt(args: Vec<Node>) -> Node {
-    let mut args = args;
-    // 末尾の nil を削除
-    args.pop();
-    if args.len() < 2 {
-        return Node::Error(format!(
-            "`>`: shortage of the numbers of arguments {}",
-            args.len()
-        ));
-    }
-    if let Node::Int(fst_num) = args[0].clone() {
-        args.remove(0);
-        let mut prev_num = fst_num;
-        for arg in args {
-            match arg {
-                Node::Int(num) => {
-                    if prev_num > num {
-                        prev_num = num;
-                        continue;
-                    } else {
-                        return Node::Bool(false);
-                    }
-                }
-                Node::Error(_) => return arg,
-                _ => {
-                    return Node::Error(format!(
-                        "cannot apply `>` for non-integer object: {}",
-                        arg.inspect()
-                    ))
-                }
-            }
-        }
-        Node::Bool(true)
+pub fn prim_char_upcase(args: &[Node]) -> Result<Node, RuntimeError> {
+    let ch: char = convert_arg("char-upcase", 1, &args[0])?;
+    Ok(Node::Char(convert_case(ch, ch.to_uppercase())))
+}
+
+pub fn prim_char_downcase(args: &[Node]) -> Result<Node, RuntimeError> {
+    let ch: char = convert_arg("char-downcase", 1, &args[0])?;
+    Ok(Node::Char(convert_case(ch, ch.to_lowercase())))
+}
+
+fn char_predicate(name: &str, arg: &Node, pred: fn(char) -> bool) -> Result<Node, RuntimeError> {
+    let ch: char = convert_arg(name, 1, arg)?;
+    Ok(Node::Bool(pred(ch)))
+}
+
+pub fn prim_char_alphabetic(args: &[Node]) -> Result<Node, RuntimeError> {
+    char_predicate("char-alphabetic?", &args[0], char::is_alphabetic)
+}
+
+pub fn prim_char_numeric(args: &[Node]) -> Result<Node, RuntimeError> {
+    char_predicate("char-numeric?", &args[0], char::is_numeric)
+}
+
+pub fn prim_char_whitespace(args: &[Node]) -> Result<Node, RuntimeError> {
+    char_predicate("char-whitespace?", &args[0], char::is_whitespace)
+}
+
+// 文字列の手続きでは、添字や長さはバイトではなく文字を単位とする
+
+fn string_arg<'a>(name: &str, position: usize, arg: &'a Node) -> Result<&'a str, RuntimeError> {
+    if let Node::Str(string) = arg {
+        Ok(string)
     } else {
-        Node::Error(format!(
-            "cannot apply `>` for non-integer object: {}",
-            args[0].inspect()
-        ))
+        Err(wrong_type(name, position, "string", arg))
     }
 }
 
-pub fn prim_le(args: Vec<Node>) -> Node {
-    let mut args = args;
-    // 末尾の nil を削除
-    args.pop();
-    if args.len() < 2 {
-        return Node::Error(format!(
-            "`<=`: shortage of the numbers of arguments {}",
-            args.len()
-        ));
-    }
-    if let Node::Int(fst_num) = args[0].clone() {
-        args.remove(0);
-        let mut prev_num = fst_num;
-        for arg in args {
-            match arg {
-                Node::Int(num) => {
-                    if prev_num <= num {
-                        prev_num = num;
-                        continue;
-                    } else {
-                        return Node::Bool(false);
-                    }
-                }
-                Node::Error(_) => return arg,
-                _ => {
-                    return Node::Error(format!(
-                        "cannot apply `<=` for non-integer object: {}",
-                        arg.inspect()
-                    ))
-                }
-            }
-        }
-        Node::Bool(true)
+/// `0..=max` の範囲にある添字を取り出す
+fn index_arg(name: &str, position: usize, arg: &Node, max: usize) -> Result<usize, RuntimeError> {
+    let index: i64 = convert_arg(name, position, arg)?;
+    if 0 <= index && index as usize <= max {
+        Ok(index as usize)
     } else {
-        Node::Error(format!(
-            "cannot apply `<=` for non-integer object: {}",
-            args[0].inspect()
-        ))
+        Err(RuntimeError::OutOfRange {
+            name: name.to_string(),
+            position,
+            given: arg.inspect(),
+        })
     }
 }
 
-pub fn prim_ge(args: Vec<Node>) -> Node {
-    let mut args = args;
-    // 末尾の nil を削除
-    args.pop();
-    if args.len() < 2 {
-        return Node::Error(format!(
-            "`>=`: shortage of the numbers of arguments {}",
-            args.len()
-        ));
+/// バイト単位の位置を文字単位の添字に直す
+fn char_index(string: &str, byte_index: usize) -> Node {
+    Node::Int(string[..byte_index].chars().count() as i64)
+}
+
+pub fn prim_string(args: &[Node]) -> Result<Node, RuntimeError> {
+    Ok(Node::Bool(matches!(args[0], Node::Str(_))))
+}
+
+pub fn prim_string_length(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string-length", 1, &args[0])?;
+    Ok(Node::Int(string.chars().count() as i64))
+}
+
+pub fn prim_string_ref(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string-ref", 1, &args[0])?;
+    let len = string.chars().count();
+    // 空文字列にはどの添字も範囲外になる
+    let k = index_arg("string-ref", 2, &args[1], len.saturating_sub(1))?;
+    match string.chars().nth(k) {
+        Some(ch) => Ok(Node::Char(ch)),
+        None => Err(RuntimeError::OutOfRange {
+            name: "string-ref".to_string(),
+            position: 2,
+            given: args[1].inspect(),
+        }),
     }
-    if let Node::Int(fst_num) = args[0].clone() {
-        args.remove(0);
-        let mut prev_num = fst_num;
-        for arg in args {
-            match arg {
-                Node::Int(num) => {
-                    if prev_num >= num {
-                        prev_num = num;
-                        continue;
-                    } else {
-                        return Node::Bool(false);
-                    }
-                }
-                Node::Error(_) => return arg,
-                _ => {
-                    return Node::Error(format!(
-                        "cannot apply `>=` for non-integer object: {}",
-                        arg.inspect()
-                    ))
-                }
-            }
-        }
-        Node::Bool(true)
-    } else {
-        Node::Error(format!(
-            "cannot apply `>=` for non-integer object: {}",
-            args[0].inspect()
-        ))
+}
+
+/// 終了位置を省略すると文字列の末尾までを取り出す
+pub fn prim_substring(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("substring", 1, &args[0])?;
+    let len = string.chars().count();
+    let end = match args.get(2) {
+        Some(end) => index_arg("substring", 3, end, len)?,
+        None => len,
+    };
+    let start = index_arg("substring", 2, &args[1], end)?;
+    Ok(Node::Str(
+        string.chars().skip(start).take(end - start).collect(),
+    ))
+}
+
+pub fn prim_string_append(args: &[Node]) -> Result<Node, RuntimeError> {
+    let mut result = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        result.push_str(string_arg("string-append", i + 1, arg)?);
     }
+    Ok(Node::Str(result))
+}
+
+fn compare_strings(
+    name: &str,
+    args: &[Node],
+    pred: fn(&str, &str) -> bool,
+) -> Result<Node, RuntimeError> {
+    let strings = args
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| string_arg(name, i + 1, arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Node::Bool(
+        strings.windows(2).all(|pair| pred(pair[0], pair[1])),
+    ))
+}
+
+pub fn prim_string_equal(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_strings("string=?", args, |a, b| a == b)
+}
+
+// UTF-8 のバイト列の順序は符号位置の順序と一致する
+pub fn prim_string_lt(args: &[Node]) -> Result<Node, RuntimeError> {
+    compare_strings("string<?", args, |a, b| a < b)
+}
+
+pub fn prim_string_to_list(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string->list", 1, &args[0])?;
+    Ok(Node::list(string.chars().map(Node::Char).collect()))
+}
+
+pub fn prim_list_to_string(args: &[Node]) -> Result<Node, RuntimeError> {
+    let chars: Vec<char> = convert_arg("list->string", 1, &args[0])?;
+    Ok(Node::Str(chars.into_iter().collect()))
+}
+
+pub fn prim_string_upcase(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string-upcase", 1, &args[0])?;
+    Ok(Node::Str(string.to_uppercase()))
+}
+
+pub fn prim_string_downcase(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string-downcase", 1, &args[0])?;
+    Ok(Node::Str(string.to_lowercase()))
+}
+
+/// 文字が最初に現れる位置を返す。見つからなければ `#f`
+pub fn prim_string_index(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string-index", 1, &args[0])?;
+    let ch: char = convert_arg("string-index", 2, &args[1])?;
+    Ok(match string.chars().position(|c| c == ch) {
+        Some(i) => Node::Int(i as i64),
+        None => Node::Bool(false),
+    })
+}
+
+/// 部分文字列が最初に現れる位置を返す。見つからなければ `#f`
+pub fn prim_string_contains(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string-contains", 1, &args[0])?;
+    let pattern = string_arg("string-contains", 2, &args[1])?;
+    Ok(match string.find(pattern) {
+        Some(i) => char_index(string, i),
+        None => Node::Bool(false),
+    })
+}
+
+/// 区切りの文字または文字列で分割したリストを返す。
+/// 区切りを省略すると空白の並びで分割し、空の要素は含めない
+pub fn prim_string_split(args: &[Node]) -> Result<Node, RuntimeError> {
+    let string = string_arg("string-split", 1, &args[0])?;
+    let fields: Vec<&str> = match args.get(1) {
+        None => string.split_whitespace().collect(),
+        Some(Node::Char(ch)) => string.split(*ch).collect(),
+        Some(Node::Str(delim)) if !delim.is_empty() => string.split(delim.as_str()).collect(),
+        Some(other) => {
+            return Err(wrong_type(
+                "string-split",
+                2,
+                "char or non-empty string",
+                other,
+            ))
+        }
+    };
+    Ok(Node::list(
+        fields
+            .into_iter()
+            .map(|field| Node::Str(field.to_string()))
+            .collect(),
+    ))
+}
+
+/// 文字列のリストを区切り（省略時は空白一つ）を挟んで連結する
+pub fn prim_string_join(args: &[Node]) -> Result<Node, RuntimeError> {
+    let strings: Vec<String> = convert_arg("string-join", 1, &args[0])?;
+    let delim = match args.get(1) {
+        Some(delim) => string_arg("string-join", 2, delim)?,
+        None => " ",
+    };
+    Ok(Node::Str(strings.join(delim)))
 }
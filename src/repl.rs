@@ -1,42 +1,464 @@
-use std::io::{self, Write};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
 
-use crate::{compiler::Compiler, env::init_global_env, lexer::Lexer, parser::Parser, vm::VM};
+use rustyline::{
+    completion::Completer,
+    error::ReadlineError,
+    highlight::{CmdKind, Highlighter, MatchingBracketHighlighter},
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
 
-pub fn repl(debug: bool, sources: Option<Vec<String>>) {
-    let mut global_env = init_global_env(sources);
+use crate::{
+    ast::Node,
+    compiler::{macroexpand, Compiler, SPECIAL_FORMS},
+    disasm::disassemble,
+    env::{init_global_env, GlobalEnv},
+    error::RuntimeError,
+    heap,
+    inst::Inst,
+    lexer::Lexer,
+    parser::Parser,
+    span::{Located, Source, SpanTable},
+    symbol::Symbol,
+    util::get_source,
+    vm::VM,
+};
+
+/// 行編集での補完と括弧の強調表示
+struct ReplHelper {
+    // 補完の候補となる識別子（大域変数と特殊形式）
+    names: Vec<String>,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl ReplHelper {
+    fn new() -> Self {
+        ReplHelper {
+            names: Vec::new(),
+            brackets: MatchingBracketHighlighter::new(),
+        }
+    }
+
+    /// 補完の候補を大域環境の現在の内容に合わせる
+    fn update_names(&mut self, global_env: &GlobalEnv) {
+        let mut names: Vec<String> = global_env
+            .names()
+            .map(|sym| sym.to_string())
+            .chain(SPECIAL_FORMS.iter().map(|name| name.to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+        self.names = names;
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete_ident(&self.names, line, pos))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        self.brackets.highlight_char(line, pos, kind)
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// カーソル位置の直前にある識別子の断片を補完する。
+/// 断片の開始位置と、その断片で始まる名前を返す
+fn complete_ident(names: &[String], line: &str, pos: usize) -> (usize, Vec<String>) {
+    let start = line[..pos]
+        .rfind(|ch: char| ch.is_whitespace() || "()[]'`,\"".contains(ch))
+        .map_or(0, |i| i + 1);
+    let prefix = &line[start..pos];
+    let candidates = names
+        .iter()
+        .filter(|name| name.starts_with(prefix))
+        .cloned()
+        .collect();
+    (start, candidates)
+}
+
+/// 入力履歴を保存するファイル
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".fzscheme_history"))
+}
+
+/// REPL の状態
+struct Repl {
+    global_env: GlobalEnv,
+    // `,reset` で読み込み直すライブラリ
+    sources: Option<Vec<String>>,
+    debug: bool,
+    // `exit` が呼ばれたときの終了ステータス
+    exit: Option<i32>,
+}
+
+/// 入力を処理した後に REPL を続けるかどうか
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    /// 終了ステータスとともに REPL を終える
+    Quit(i32),
+}
+
+const HELP: &str = "\
+,help              show this help
+,disasm <expr>     show the compiled VM code of <expr>
+,expand <expr>     show the macro expansion of <expr>
+,time <expr>       evaluate <expr> and show the elapsed time
+,load <file>       load a scheme source file
+,env               list the global variables
+,gc                collect garbage and show heap statistics
+,reset             reinitialize the global environment
+,debug on|off      toggle showing VM code
+(quit), (exit [n])  exit the REPL";
+
+/// REPL を実行し、終了ステータスを返す
+pub fn repl(debug: bool, sources: Option<Vec<String>>) -> i32 {
+    let mut repl = Repl {
+        global_env: init_global_env(sources.clone()),
+        sources,
+        debug,
+        exit: None,
+    };
+    let mut status = 0;
+    // 以前の入力で定義した手続きのエラーも指せるよう、入力はすべて残しておく
+    let mut source = Source::new("<stdin>", "");
+    // 式が閉じるまで読み溜めている入力と、その先頭の行番号
+    let mut input = String::new();
+    let mut first_line = 0;
+
+    let mut editor: Editor<ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            println!("failed to initialize the line editor: {}", err);
+            return 1;
+        }
+    };
+    let mut helper = ReplHelper::new();
+    helper.update_names(&repl.global_env);
+    editor.set_helper(Some(helper));
+    let history = history_path();
+    if let Some(path) = &history {
+        // 初回起動時などで履歴がなくても構わない
+        let _ = editor.load_history(path);
+    }
 
     loop {
-        print!(">>> ");
-        io::stdout().flush().unwrap();
-        let stdin = io::stdin();
-        let mut line = String::new();
-        stdin.read_line(&mut line).unwrap();
+        let prompt = if input.is_empty() { ">>> " } else { "... " };
+        let mut line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C で入力途中の式を捨てる
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            // EOF
+            Err(ReadlineError::Eof) => {
+                println!();
+                if !input.is_empty() {
+                    // 閉じられないまま終わった入力を報告する
+                    repl.eval_input(&input, first_line, &source);
+                }
+                break;
+            }
+            Err(err) => {
+                println!("input error: {}", err);
+                continue;
+            }
+        };
+        line.push('\n');
 
-        if line.contains("(quit)") || line.contains("(exit)") {
+        let line_no = source.append(&line);
+        if input.is_empty() {
+            first_line = line_no;
+        }
+        input.push_str(&line);
+
+        // 式が途中であれば続きの行を読む。コマンドは 1 行に収める
+        if parse_command(&input).is_none() {
+            let mut p = Parser::new(Lexer::with_line(&input, first_line));
+            if matches!(p.parse(), Err(err) if err.error.is_incomplete()) {
+                continue;
+            }
+        }
+
+        if !input.trim().is_empty() {
+            let _ = editor.add_history_entry(input.trim_end());
+        }
+        let flow = match parse_command(&input) {
+            Some((name, arg, offset)) => {
+                // 引数の桁位置がずれないよう、コマンド名の部分を空白で埋める
+                let padded = format!("{}{}", " ".repeat(offset), arg);
+                repl.run_command(name, &padded, first_line, &source)
+            }
+            None => repl.eval_input(&input, first_line, &source),
+        };
+        input.clear();
+        if let Flow::Quit(exit_status) = flow {
+            status = exit_status;
             break;
         }
+        if let Some(helper) = editor.helper_mut() {
+            helper.update_names(&repl.global_env);
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(err) = editor.save_history(path) {
+            println!("failed to save the history: {}", err);
+        }
+    }
+    status
+}
+
+/// `,name arg` の形のコマンドを名前と引数、引数の開始位置に分ける
+fn parse_command(input: &str) -> Option<(&str, &str, usize)> {
+    let trimmed = input.trim_start();
+    let body = trimmed.strip_prefix(',')?;
+    let name_len = body.find(char::is_whitespace).unwrap_or(body.len());
+    let offset = input.len() - body.len() + name_len;
+    Some((&body[..name_len], &input[offset..], offset))
+}
+
+/// 構文解析に失敗すればエラーを表示して `None` を返す。
+/// 各式には位置の対応表を添える
+fn parse(text: &str, first_line: usize, source: &Source) -> Option<Vec<(Node, Rc<SpanTable>)>> {
+    let mut p = Parser::new(Lexer::with_line(text, first_line));
+    match p.parse() {
+        Ok(nodes) => {
+            let spans = Rc::new(p.into_spans());
+            Some(
+                nodes
+                    .into_iter()
+                    .map(|node| (node, spans.clone()))
+                    .collect(),
+            )
+        }
+        Err(err) => {
+            println!("parse error: {}", source.describe(err.error, err.span));
+            None
+        }
+    }
+}
+
+/// `(quit)` の式か。`(exit)` は手続きとして評価する
+fn is_quit(node: &Node) -> bool {
+    matches!(
+        node.to_vec().as_deref(),
+        Some([Node::Ident(name)]) if name == "quit"
+    )
+}
+
+impl Repl {
+    /// 入力中の式を順に評価して結果を表示する
+    fn eval_input(&mut self, input: &str, first_line: usize, source: &Source) -> Flow {
+        let nodes = match parse(input, first_line, source) {
+            Some(nodes) => nodes,
+            None => return Flow::Continue,
+        };
+        for (node, spans) in nodes {
+            if is_quit(&node) {
+                return Flow::Quit(0);
+            }
+            if let Some(result) = self.eval(node, &spans, source) {
+                println!("==> {}", result.inspect());
+            }
+            if let Some(status) = self.exit {
+                return Flow::Quit(status);
+            }
+        }
+        Flow::Continue
+    }
+
+    /// 式をコンパイルして実行する。失敗すればエラーを表示して `None` を返す。
+    /// `exit` が呼ばれた場合は終了ステータスを記録して `None` を返す
+    fn eval(&mut self, node: Node, spans: &SpanTable, source: &Source) -> Option<Node> {
+        let code = self.compile(node, spans, source)?;
+        if self.debug {
+            println!("VM code:\n\n{}", disassemble(&code));
+        }
+        match VM::new(code).run(&mut self.global_env) {
+            Ok(result) => Some(result),
+            Err(Located {
+                error: RuntimeError::Exit(status),
+                ..
+            }) => {
+                self.exit = Some(status);
+                None
+            }
+            Err(err) => {
+                println!("runtime error: {}", source.describe(err.error, err.span));
+                None
+            }
+        }
+    }
+
+    fn compile(&mut self, node: Node, spans: &SpanTable, source: &Source) -> Option<Vec<Inst>> {
+        match Compiler::with_spans(node, spans).compile(&mut self.global_env) {
+            Ok(code) => Some(code),
+            Err(err) => {
+                println!("compile error: {}", source.describe(err.error, err.span));
+                None
+            }
+        }
+    }
 
-        let lex = Lexer::new(&line);
-        let mut p = Parser::new(lex);
-        match p.parse() {
-            Ok(nodes) => {
-                for node in nodes {
-                    let comp = Compiler::new(node);
-                    match comp.compile(&mut global_env) {
-                        Ok(code) => {
-                            if debug {
-                                println!("VM code:\n\n{:?}\n", code);
+    fn run_command(&mut self, name: &str, arg: &str, first_line: usize, source: &Source) -> Flow {
+        match name {
+            "help" => println!("{}", HELP),
+            "disasm" => {
+                for (node, spans) in parse(arg, first_line, source).unwrap_or_default() {
+                    if let Some(code) = self.compile(node, &spans, source) {
+                        print!("{}", disassemble(&code));
+                    }
+                }
+            }
+            "expand" => {
+                for (node, _) in parse(arg, first_line, source).unwrap_or_default() {
+                    match macroexpand(node, &mut self.global_env) {
+                        Ok(expanded) => println!("==> {}", expanded.inspect()),
+                        Err(err) => println!("compile error: {}", err),
+                    }
+                }
+            }
+            "time" => {
+                if let Some(nodes) = parse(arg, first_line, source) {
+                    let start = Instant::now();
+                    for (node, spans) in nodes {
+                        if let Some(result) = self.eval(node, &spans, source) {
+                            println!("==> {}", result.inspect());
+                        }
+                    }
+                    println!("elapsed: {:?}", start.elapsed());
+                }
+            }
+            "load" => {
+                let path = PathBuf::from(arg.trim());
+                match get_source(&path) {
+                    Ok(text) => {
+                        let file = Source::new(path.display().to_string(), text);
+                        for (node, spans) in parse(file.text(), 1, &file).unwrap_or_default() {
+                            if self.eval(node, &spans, &file).is_none() {
+                                break;
                             }
-                            let result = VM::new(code).run(&mut global_env);
-                            print!("==> ");
-                            io::stdout().flush().unwrap();
-                            println!("{}", result.inspect());
                         }
-                        Err(msg) => println!("compile error: {}", msg),
                     }
+                    Err(msg) => println!("load error: {}", msg),
                 }
             }
-            Err(msg) => println!("parse error: {}", msg),
+            "gc" => {
+                let freed = heap::collect();
+                let stats = heap::stats();
+                println!(
+                    "freed {} frames ({} live, {} allocated, {} collections)",
+                    freed, stats.frames, stats.allocated, stats.collections
+                );
+            }
+            "env" => {
+                let mut names: Vec<Rc<str>> = self.global_env.names().map(Symbol::name).collect();
+                names.sort();
+                let mut line = String::new();
+                for name in names {
+                    if !line.is_empty() && line.len() + name.len() >= 80 {
+                        println!("{}", line);
+                        line.clear();
+                    }
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(&name);
+                }
+                println!("{}", line);
+            }
+            "reset" => {
+                self.global_env = init_global_env(self.sources.clone());
+                println!("global environment is reinitialized.");
+            }
+            "debug" => match arg.trim() {
+                "on" => self.debug = true,
+                "off" => self.debug = false,
+                "" => println!("debug: {}", if self.debug { "on" } else { "off" }),
+                _ => println!("usage: ,debug on|off"),
+            },
+            _ => println!("unknown command: ,{} (see ,help)", name),
         }
+        self.exit.map_or(Flow::Continue, Flow::Quit)
+    }
+}
+
+#[cfg(test)]
+mod repl_test {
+    use super::{complete_ident, is_quit, parse_command};
+    use crate::{lexer::Lexer, parser::Parser};
+
+    #[test]
+    fn complete_ident_test() {
+        let names: Vec<String> = ["car", "cdr", "cons", "define", "define-macro"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(
+            complete_ident(&names, "(c", 2),
+            (
+                1,
+                vec!["car".to_string(), "cdr".to_string(), "cons".to_string()]
+            )
+        );
+        assert_eq!(
+            complete_ident(&names, "(foo '(defi x)", 11),
+            (7, vec!["define".to_string(), "define-macro".to_string()])
+        );
+        assert_eq!(
+            complete_ident(&names, "co", 2),
+            (0, vec!["cons".to_string()])
+        );
+        assert_eq!(complete_ident(&names, "(x", 2), (1, vec![]));
+    }
+
+    #[test]
+    fn parse_command_test() {
+        assert_eq!(parse_command(",help\n"), Some(("help", "\n", 5)));
+        assert_eq!(
+            parse_command("  ,expand (my-or a b)\n"),
+            Some(("expand", " (my-or a b)\n", 9))
+        );
+        assert_eq!(parse_command("(display \",help\")"), None);
+    }
+
+    #[test]
+    fn is_quit_test() {
+        let nodes = Parser::new(Lexer::new(
+            "(quit) (exit) (display \"(quit)\") (quit 1) quit",
+        ))
+        .parse()
+        .unwrap();
+        let quits: Vec<bool> = nodes.iter().map(is_quit).collect();
+        assert_eq!(quits, vec![true, false, false, false, false]);
     }
 }
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::Node;
+
+/// ソースコード上の位置（行・桁とも 1 始まり）
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, col: usize) -> Self {
+        Span { line, col }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// 位置情報の付いたエラー
+#[derive(Debug, Clone, PartialEq)]
+pub struct Located<E> {
+    pub error: E,
+    pub span: Option<Span>,
+}
+
+impl<E> Located<E> {
+    pub fn new(error: E, span: Option<Span>) -> Self {
+        Located { error, span }
+    }
+
+    /// まだ位置が決まっていなければ `span` を位置とする
+    pub fn or_span(mut self, span: Option<Span>) -> Self {
+        if self.span.is_none() {
+            self.span = span;
+        }
+        self
+    }
+}
+
+impl<E: Error> Error for Located<E> {}
+
+impl<E: fmt::Display> fmt::Display for Located<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+/// 構文解析で作られたリストと、その開き括弧の位置との対応表。
+/// ペアはセルの同一性で区別する。
+#[derive(Debug, Default)]
+pub struct SpanTable {
+    // 登録したセルが解放されてアドレスが再利用されないよう、ノード自体も保持しておく
+    spans: HashMap<usize, (Node, Span)>,
+}
+
+impl SpanTable {
+    pub fn new() -> Self {
+        SpanTable {
+            spans: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, node: &Node, span: Span) {
+        if let Some(id) = node.pair_id() {
+            self.spans.insert(id, (node.clone(), span));
+        }
+    }
+
+    pub fn get(&self, node: &Node) -> Option<Span> {
+        node.pair_id()
+            .and_then(|id| self.spans.get(&id))
+            .map(|(_, span)| *span)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
+
+/// エラー表示のためのソースコードとその名前（ファイル名など）
+#[derive(Debug, Clone)]
+pub struct Source {
+    name: String,
+    text: String,
+}
+
+impl Source {
+    pub fn new<S: Into<String>, T: Into<String>>(name: S, text: T) -> Self {
+        Source {
+            name: name.into(),
+            text: text.into(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// ソースコードを末尾に追加し、追加した部分の先頭の行番号を返す
+    pub fn append(&mut self, text: &str) -> usize {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        let line = self.text.lines().count() + 1;
+        self.text.push_str(text);
+        line
+    }
+
+    /// `file.scm:12:5: msg` の形式でエラーを整形し、該当する行を添える
+    pub fn describe<M: fmt::Display>(&self, msg: M, span: Option<Span>) -> String {
+        let span = match span {
+            Some(span) => span,
+            None => return format!("{}: {}", self.name, msg),
+        };
+        let mut result = format!("{}:{}: {}", self.name, span, msg);
+        if let Some(line) = self.text.lines().nth(span.line - 1) {
+            let line_no = span.line.to_string();
+            result.push_str(&format!("\n {} | {}", line_no, line));
+            result.push_str(&format!(
+                "\n {} | {}^",
+                " ".repeat(line_no.len()),
+                " ".repeat(span.col - 1)
+            ));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod span_test {
+    use super::{Source, Span};
+
+    #[test]
+    fn describe_test() {
+        let source = Source::new("test.scm", "(define x 1)\n  (car x)\n");
+        let expected = "test.scm:2:3: car: argument is not pair: 1\n 2 |   (car x)\n   |   ^";
+        assert_eq!(
+            source.describe("car: argument is not pair: 1", Some(Span::new(2, 3))),
+            expected
+        );
+        assert_eq!(source.describe("error", None), "test.scm: error");
+    }
+}
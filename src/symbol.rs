@@ -0,0 +1,160 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// 名前を登録したシンボル。同じ名前からは常に同じ番号のシンボルが得られるので、
+/// 比較やハッシュは番号だけで済む
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+// 処理系が特別扱いするシンボル。表を作るときに先頭から順に登録するので番号は固定になる
+macro_rules! well_known_symbols {
+    ($($konst:ident = $id:expr, $name:expr;)*) => {
+        impl Symbol {
+            $(pub const $konst: Symbol = Symbol($id);)*
+        }
+
+        const WELL_KNOWN: &[(Symbol, &str)] = &[$((Symbol::$konst, $name)),*];
+    };
+}
+
+well_known_symbols! {
+    QUOTE = 0, "quote";
+    IF = 1, "if";
+    LAMBDA = 2, "lambda";
+    DEFINE = 3, "define";
+    DEFINE_MACRO = 4, "define-macro";
+    SET = 5, "set!";
+    DEFINE_SYNTAX = 6, "define-syntax";
+    LET_SYNTAX = 7, "let-syntax";
+    LETREC_SYNTAX = 8, "letrec-syntax";
+    SYNTAX_RULES = 9, "syntax-rules";
+    ELLIPSIS = 10, "...";
+    UNDERSCORE = 11, "_";
+}
+
+struct SymbolTable {
+    // 番号から名前への対応
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, Symbol>,
+    // gensym で作ったシンボルの数
+    generated: usize,
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable::new());
+}
+
+impl SymbolTable {
+    fn new() -> Self {
+        let mut table = SymbolTable {
+            names: Vec::new(),
+            ids: HashMap::new(),
+            generated: 0,
+        };
+        for (sym, name) in WELL_KNOWN {
+            let added = table.intern(name);
+            debug_assert_eq!(added, *sym, "well-known symbol out of order: {}", name);
+        }
+        table
+    }
+
+    fn add(&mut self, name: Rc<str>) -> Symbol {
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name);
+        sym
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&sym) = self.ids.get(name) {
+            return sym;
+        }
+        let name: Rc<str> = Rc::from(name);
+        let sym = self.add(name.clone());
+        self.ids.insert(name, sym);
+        sym
+    }
+}
+
+impl Symbol {
+    /// 名前に対応するシンボルを返す。初めての名前であれば登録する
+    pub fn intern(name: &str) -> Self {
+        SYMBOLS.with(|table| table.borrow_mut().intern(name))
+    }
+
+    /// どの名前からも得られない新しいシンボルを作る。名前は表示にだけ使う
+    pub fn gensym(prefix: &str) -> Self {
+        SYMBOLS.with(|table| {
+            let mut table = table.borrow_mut();
+            table.generated += 1;
+            let name = format!("{}{}", prefix, table.generated);
+            table.add(Rc::from(name))
+        })
+    }
+
+    pub fn name(self) -> Rc<str> {
+        SYMBOLS.with(|table| table.borrow().names[self.id()].clone())
+    }
+
+    /// 0 から順に振られる番号
+    pub fn id(self) -> usize {
+        self.0 as usize
+    }
+
+    /// `id` で得た番号からシンボルに戻す
+    pub fn from_id(id: usize) -> Self {
+        Symbol(id as u32)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({:?})", &*self.name())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.name() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.name() == *other
+    }
+}
+
+#[cfg(test)]
+mod symbol_test {
+    use super::{Symbol, WELL_KNOWN};
+
+    #[test]
+    fn intern_test() {
+        let a = Symbol::intern("lambda");
+        assert_eq!(a, Symbol::intern("lambda"));
+        assert_ne!(a, Symbol::intern("lambda*"));
+        assert_eq!(&*a.name(), "lambda");
+        assert!(a == "lambda");
+
+        // gensym のシンボルは同じ名前を登録しても一致しない
+        let g = Symbol::gensym("g");
+        assert_ne!(g, Symbol::intern(&g.name()));
+        assert_ne!(g, Symbol::gensym("g"));
+    }
+
+    #[test]
+    fn well_known_test() {
+        for (sym, name) in WELL_KNOWN {
+            assert_eq!(Symbol::intern(name), *sym);
+        }
+        assert_eq!(Symbol::intern("set!"), Symbol::SET);
+    }
+}
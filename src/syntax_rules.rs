@@ -0,0 +1,403 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{ast::Node, env::Env, symbol::Symbol};
+
+static RENAME_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// マクロを定義した時点のコンパイル時環境。
+/// 環境は循環しうるので、比較はポインタの同一性で行う。
+#[derive(Clone)]
+pub struct SyntaxEnv(pub Rc<RefCell<Env>>);
+
+impl PartialEq for SyntaxEnv {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SyntaxEnv {}
+
+impl fmt::Debug for SyntaxEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<syntax-env>")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxRules {
+    ellipsis: Node,
+    literals: Vec<Node>,
+    rules: Vec<(Node, Node)>,
+    env: SyntaxEnv,
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    One(Node),
+    Many(Vec<Binding>),
+}
+
+type Bindings = Vec<(Node, Binding)>;
+
+impl SyntaxRules {
+    /// `(syntax-rules (literal ...) (pattern template) ...)` または
+    /// `(syntax-rules ellipsis (literal ...) (pattern template) ...)` を解釈する。
+    pub fn new(spec: &Node, env: Rc<RefCell<Env>>) -> Result<Self, &'static str> {
+        let (mut items, tail) = spec.list_parts();
+        if !tail.is_null() || items.len() < 2 {
+            return Err("malformed `syntax-rules`.");
+        }
+        items.remove(0);
+        let ellipsis = if is_identifier(&items[0]) {
+            items.remove(0)
+        } else {
+            Node::Ident(Symbol::ELLIPSIS)
+        };
+        if items.is_empty() {
+            return Err("malformed `syntax-rules`.");
+        }
+        let (literals, tail) = items.remove(0).list_parts();
+        if !tail.is_null() || !literals.iter().all(is_identifier) {
+            return Err("literals of `syntax-rules` must be a list of symbols.");
+        }
+        let mut rules = Vec::new();
+        for rule in items {
+            let (rule, tail) = rule.list_parts();
+            if !tail.is_null() || rule.len() != 2 {
+                return Err("each rule of `syntax-rules` must be (pattern template).");
+            }
+            if !rule[0].is_pair() {
+                return Err("pattern of `syntax-rules` must be a list.");
+            }
+            rules.push((rule[0].clone(), rule[1].clone()));
+        }
+        Ok(SyntaxRules {
+            ellipsis,
+            literals,
+            rules,
+            env: SyntaxEnv(env),
+        })
+    }
+
+    /// マクロ呼び出しの式 `form` を展開する。
+    /// テンプレート中で導入された識別子は、すべて新しい `Node::Renamed` に置き換える。
+    pub fn expand(&self, form: &Node) -> Result<Node, &'static str> {
+        let form_args = form.cdr().unwrap();
+        for (pattern, template) in &self.rules {
+            // パターンの先頭（マクロのキーワード）は照合しない
+            let pattern_args = pattern.cdr().unwrap();
+            let mut binds = Bindings::new();
+            if self.match_pattern(&pattern_args, &form_args, &mut binds) {
+                let mut renames = Vec::new();
+                return self.expand_template(template, &binds, &mut renames, false);
+            }
+        }
+        Err("no syntax rule matches the form.")
+    }
+
+    fn is_ellipsis(&self, node: &Node) -> bool {
+        *node == self.ellipsis
+    }
+
+    fn match_pattern(&self, pattern: &Node, form: &Node, binds: &mut Bindings) -> bool {
+        if is_identifier(pattern) {
+            if self.literals.contains(pattern) {
+                is_identifier(form) && strip(form) == strip(pattern)
+            } else {
+                if strip(pattern) != Node::Ident(Symbol::UNDERSCORE) {
+                    binds.push((pattern.clone(), Binding::One(form.clone())));
+                }
+                true
+            }
+        } else if pattern.is_pair() || pattern.is_null() {
+            let (patterns, pattern_tail) = pattern.list_parts();
+            let (forms, form_tail) = form.list_parts();
+            let ellipsis_pos = patterns
+                .iter()
+                .position(|pattern| self.is_ellipsis(pattern))
+                .filter(|pos| *pos > 0)
+                .map(|pos| pos - 1);
+            if let Some(pos) = ellipsis_pos {
+                let before = &patterns[..pos];
+                let after = &patterns[(pos + 2)..];
+                if forms.len() < before.len() + after.len() {
+                    return false;
+                }
+                let repeat = forms.len() - before.len() - after.len();
+                for (pattern, form) in before.iter().zip(forms.iter()) {
+                    if !self.match_pattern(pattern, form, binds) {
+                        return false;
+                    }
+                }
+                let mut matches = Vec::new();
+                for form in &forms[before.len()..(before.len() + repeat)] {
+                    let mut iter_binds = Bindings::new();
+                    if !self.match_pattern(&patterns[pos], form, &mut iter_binds) {
+                        return false;
+                    }
+                    matches.push(iter_binds);
+                }
+                for var in self.pattern_vars(&patterns[pos]) {
+                    let seq = matches
+                        .iter()
+                        .map(|iter_binds| lookup(&var, iter_binds).unwrap().clone())
+                        .collect();
+                    binds.push((var, Binding::Many(seq)));
+                }
+                for (pattern, form) in after.iter().zip(forms[(before.len() + repeat)..].iter()) {
+                    if !self.match_pattern(pattern, form, binds) {
+                        return false;
+                    }
+                }
+                self.match_pattern(&pattern_tail, &form_tail, binds)
+            } else {
+                if forms.len() < patterns.len() {
+                    return false;
+                }
+                for (pattern, form) in patterns.iter().zip(forms.iter()) {
+                    if !self.match_pattern(pattern, form, binds) {
+                        return false;
+                    }
+                }
+                let rest = Node::list_with_tail(forms[patterns.len()..].to_vec(), form_tail);
+                if pattern_tail.is_null() {
+                    rest.is_null()
+                } else {
+                    self.match_pattern(&pattern_tail, &rest, binds)
+                }
+            }
+        } else {
+            strip(pattern) == strip(form)
+        }
+    }
+
+    fn pattern_vars(&self, pattern: &Node) -> Vec<Node> {
+        if is_identifier(pattern) {
+            if self.literals.contains(pattern)
+                || self.is_ellipsis(pattern)
+                || strip(pattern) == Node::Ident(Symbol::UNDERSCORE)
+            {
+                Vec::new()
+            } else {
+                vec![pattern.clone()]
+            }
+        } else if pattern.is_pair() {
+            let (items, tail) = pattern.list_parts();
+            items
+                .iter()
+                .chain(std::iter::once(&tail))
+                .flat_map(|node| self.pattern_vars(node))
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn expand_template(
+        &self,
+        template: &Node,
+        binds: &Bindings,
+        renames: &mut Vec<(Node, Node)>,
+        escaped: bool,
+    ) -> Result<Node, &'static str> {
+        if is_identifier(template) {
+            match lookup(template, binds) {
+                Some(Binding::One(node)) => Ok(node.clone()),
+                Some(Binding::Many(_)) => Err("pattern variable is used without ellipsis."),
+                None => {
+                    if let Some((_, renamed)) = renames.iter().find(|(name, _)| name == template) {
+                        Ok(renamed.clone())
+                    } else {
+                        let renamed = Node::Renamed(
+                            Box::new(template.clone()),
+                            RENAME_COUNTER.fetch_add(1, Ordering::Relaxed),
+                            self.env.clone(),
+                        );
+                        renames.push((template.clone(), renamed.clone()));
+                        Ok(renamed)
+                    }
+                }
+            }
+        } else if template.is_pair() {
+            let (items, tail) = template.list_parts();
+            // (... template) はテンプレート中の省略記号をそのまま出力する
+            if !escaped && items.len() == 2 && tail.is_null() && self.is_ellipsis(&items[0]) {
+                return self.expand_template(&items[1], binds, renames, true);
+            }
+            let mut expanded = Vec::new();
+            let mut i = 0;
+            while i < items.len() {
+                let mut depth = 0;
+                while !escaped
+                    && i + depth + 1 < items.len()
+                    && self.is_ellipsis(&items[i + depth + 1])
+                {
+                    depth += 1;
+                }
+                if depth == 0 {
+                    expanded.push(self.expand_template(&items[i], binds, renames, escaped)?);
+                } else {
+                    expanded.extend(self.expand_ellipsis(&items[i], binds, renames, depth)?);
+                }
+                i += depth + 1;
+            }
+            let tail = self.expand_template(&tail, binds, renames, escaped)?;
+            Ok(Node::list_with_tail(expanded, tail))
+        } else {
+            Ok(template.clone())
+        }
+    }
+
+    fn expand_ellipsis(
+        &self,
+        template: &Node,
+        binds: &Bindings,
+        renames: &mut Vec<(Node, Node)>,
+        depth: usize,
+    ) -> Result<Vec<Node>, &'static str> {
+        let vars: Vec<&(Node, Binding)> = binds
+            .iter()
+            .filter(|(var, bind)| matches!(bind, Binding::Many(_)) && occurs_in(var, template))
+            .collect();
+        if vars.is_empty() {
+            return Err("no pattern variable to repeat in the ellipsis template.");
+        }
+        let len = match &vars[0].1 {
+            Binding::Many(seq) => seq.len(),
+            Binding::One(_) => unreachable!(),
+        };
+        let mut result = Vec::new();
+        for k in 0..len {
+            let mut iter_binds = binds.clone();
+            for (var, bind) in &vars {
+                if let Binding::Many(seq) = bind {
+                    if seq.len() != len {
+                        return Err("pattern variables in the ellipsis have different lengths.");
+                    }
+                    iter_binds.retain(|(name, _)| name != var);
+                    iter_binds.push((var.clone(), seq[k].clone()));
+                }
+            }
+            if depth > 1 {
+                result.extend(self.expand_ellipsis(template, &iter_binds, renames, depth - 1)?);
+            } else {
+                result.push(self.expand_template(template, &iter_binds, renames, false)?);
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn lookup<'a>(var: &Node, binds: &'a Bindings) -> Option<&'a Binding> {
+    binds
+        .iter()
+        .rev()
+        .find(|(name, _)| name == var)
+        .map(|(_, bind)| bind)
+}
+
+fn occurs_in(var: &Node, template: &Node) -> bool {
+    if var == template {
+        true
+    } else if template.is_pair() {
+        let (items, tail) = template.list_parts();
+        items.iter().any(|node| occurs_in(var, node)) || occurs_in(var, &tail)
+    } else {
+        false
+    }
+}
+
+pub fn is_identifier(node: &Node) -> bool {
+    matches!(node, Node::Ident(_) | Node::Renamed(_, _, _))
+}
+
+/// 展開で導入された識別子を元のシンボルに戻す（`quote` されたデータなどに使う）。
+pub fn strip(node: &Node) -> Node {
+    match node {
+        Node::Renamed(base, _, _) => strip(base),
+        Node::Pair(_) => {
+            let (items, tail) = node.list_parts();
+            Node::list_with_tail(items.iter().map(strip).collect(), strip(&tail))
+        }
+        _ => node.clone(),
+    }
+}
+
+#[cfg(test)]
+mod syntax_rules_test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{strip, SyntaxRules};
+    use crate::{env::Env, lexer::Lexer, parser::Parser};
+
+    fn expand_test_template(test_name: &str, rules: &str, form: &str, expected: &str) {
+        let rules = Parser::new(Lexer::new(rules)).parse().unwrap().remove(0);
+        let form = Parser::new(Lexer::new(form)).parse().unwrap().remove(0);
+        let expected = Parser::new(Lexer::new(expected)).parse().unwrap().remove(0);
+        let rules = SyntaxRules::new(&rules, Rc::new(RefCell::new(Env::new()))).unwrap();
+        let result = strip(&rules.expand(&form).unwrap());
+
+        assert_eq!(
+            result,
+            expected,
+            "{}: expected: {}, got: {}",
+            test_name,
+            expected.inspect(),
+            result.inspect()
+        );
+    }
+
+    #[test]
+    fn expand_ellipsis_test() {
+        expand_test_template(
+            "expand_ellipsis_test (source0)",
+            "(syntax-rules () ((_ (name val) ...) ((lambda (name ...) 0) val ...)))",
+            "(my-let (a 1) (b 2))",
+            "((lambda (a b) 0) 1 2)",
+        );
+        expand_test_template(
+            "expand_ellipsis_test (source1)",
+            "(syntax-rules () ((_ (a b ...) ...) '((b ... a) ...)))",
+            "(f (1 2 3) (4) (5 6))",
+            "'((2 3 1) (4) (6 5))",
+        );
+        expand_test_template(
+            "expand_ellipsis_test (source2)",
+            "(syntax-rules () ((_ a ... z) '(z a ...)))",
+            "(rot 1 2 3 4)",
+            "'(4 1 2 3)",
+        );
+        expand_test_template(
+            "expand_ellipsis_test (source3)",
+            "(syntax-rules () ((_ x) '(x (... ...))))",
+            "(esc 1)",
+            "'(1 ...)",
+        );
+    }
+
+    #[test]
+    fn expand_literal_test() {
+        let rules = "(syntax-rules (else) ((_ else e) e) ((_ c e) (if c e #f)))";
+        expand_test_template("expand_literal_test (source0)", rules, "(m else 1)", "1");
+        expand_test_template(
+            "expand_literal_test (source1)",
+            rules,
+            "(m x 1)",
+            "(if x 1 #f)",
+        );
+    }
+
+    #[test]
+    fn expand_dotted_pattern_test() {
+        expand_test_template(
+            "expand_dotted_pattern_test",
+            "(syntax-rules () ((_ a . rest) '(rest a)))",
+            "(m 1 2 3)",
+            "'((2 3) 1)",
+        );
+    }
+}
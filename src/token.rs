@@ -1,4 +1,9 @@
-#[derive(Debug, Clone, Eq, PartialEq)]
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+use crate::error::LexError;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Lparen,
     Rparen,
@@ -8,12 +13,16 @@ pub enum Token {
     False,
     Ident(String),
     Integer(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
+    Real(f64),
     Str(String),
+    Char(char),
     Quote,
     Dot,
     Quasiquote,
     Unquote,
     UnquoteSplicing,
     Eof,
-    Illegal,
+    Illegal(LexError),
 }
@@ -11,7 +11,7 @@ pub fn get_sources(paths: Vec<PathBuf>) -> Result<Vec<String>, String> {
 }
 
 pub fn get_source(path_: &PathBuf) -> Result<String, String> {
-    if let Ok(mut file) = File::open(&path_) {
+    if let Ok(mut file) = File::open(path_) {
         let mut content = String::new();
         if file.read_to_string(&mut content).is_err() {
             return Err(format!("couldn't read file: {}", &path_.display()));
@@ -2,9 +2,13 @@ use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::{
-    ast::{Node, ProcTag},
+    ast::{Arity, Control, Node, ProcTag},
     env::{Env, GlobalEnv},
-    inst::Inst,
+    error::RuntimeError,
+    heap,
+    inst::{Code, Inst},
+    span::{Located, Span},
+    symbol::Symbol,
 };
 
 pub mod secd_stack;
@@ -13,18 +17,27 @@ pub use secd_stack::*;
 #[derive(Debug)]
 pub struct VM {
     s: StackStack,
+    // 現在のフレームが使う s の底の位置
+    base: usize,
     e: EnvStack,
-    c: CodeStack,
+    c: Code,
+    // c の中で次に実行する命令の位置
+    pc: usize,
     d: DumpStack,
+    // 最後に通過した `Inst::Loc` の位置
+    loc: Option<Span>,
 }
 
 impl VM {
-    pub fn new(code: CodeStack) -> Self {
+    pub fn new<C: Into<Code>>(code: C) -> Self {
         VM {
             s: StackStack::new(),
+            base: 0,
             e: Rc::new(RefCell::new(Env::new())),
-            c: code,
+            c: code.into(),
+            pc: 0,
             d: DumpStack::new(),
+            loc: None,
         }
     }
 
@@ -36,36 +49,36 @@ impl VM {
         self.d = dump;
     }
 
-    pub fn run(&mut self, global_env: &mut GlobalEnv) -> Node {
+    pub fn max_dump_depth(&self) -> usize {
+        self.d.max_depth()
+    }
+
+    pub fn run(&mut self, global_env: &mut GlobalEnv) -> Result<Node, Located<RuntimeError>> {
         loop {
-            match self.c.pop_front().unwrap() {
+            let pc = self.pc;
+            self.pc += 1;
+            match &self.c[pc] {
                 Inst::Ld(i, j) => {
-                    let lvar = get_lvar(&self.e, i, j).unwrap();
-                    self.s.push(match lvar {
-                        Node::Closure(_, _) => StackItem::new(lvar, Some(ProcTag::Closure)),
-                        Node::Primitive(_, _) => StackItem::new(lvar, Some(ProcTag::Primitive)),
-                        _ => StackItem::new(lvar, None),
-                    });
+                    let (i, j) = (*i, *j);
+                    let lvar = match get_lvar(&self.e, i, j) {
+                        Some(lvar) => lvar,
+                        None => return Err(self.error(RuntimeError::UnboundLocal(i, j))),
+                    };
+                    let tag = tag_of(&lvar);
+                    self.s.push(StackItem::new(lvar, tag));
                 }
                 Inst::Ldc(node) => {
-                    self.s.push(StackItem::new(node, None));
+                    self.s.push(StackItem::new(node.clone(), None));
                 }
-                Inst::Ldg(node) => {
-                    if let Node::Ident(ident) = node {
-                        if let Some(item) = get_gvar(&ident, global_env) {
-                            self.s.push(item);
-                        } else {
-                            return Node::Error(format!(
-                                "symbol not found in the global environment: {}",
-                                ident
-                            ));
-                        }
-                    } else {
-                        unreachable!("opcode `ldg` treat only ident object.");
+                Inst::Ldg(sym) => match get_gvar(*sym, global_env) {
+                    Some(item) => self.s.push(item),
+                    None => {
+                        let name = sym.to_string();
+                        return Err(self.error(RuntimeError::UnboundVariable(name)));
                     }
-                }
-                Inst::Ldf(code) => self.s.push(StackItem::new(
-                    Node::Closure(code, self.e.clone()),
+                },
+                Inst::Ldf(lambda) => self.s.push(StackItem::new(
+                    Node::Closure(lambda.clone(), self.e.clone()),
                     Some(ProcTag::Closure),
                 )),
                 Inst::Lset(i, j) => {
@@ -73,134 +86,76 @@ impl VM {
                     let node = match stack_top.clone() {
                         StackItem::Closure(node)
                         | StackItem::Primitive(node)
+                        | StackItem::Continuation(node)
                         | StackItem::Other(node) => node,
                     };
-                    set_lvar(&self.e, i, j, node);
-                    self.s.push(stack_top);
-                }
-                Inst::Gset(node) => {
-                    if let Node::Ident(ident) = node {
-                        let stack_top = self.s.pop();
-                        set_gvar(&ident, stack_top.clone(), global_env);
-                        self.s.push(stack_top);
-                    } else {
-                        unreachable!("opcode `ldg` treat only ident object.");
+                    if set_lvar(&self.e, *i, *j, node).is_none() {
+                        return Err(self.error(RuntimeError::UnboundLocal(*i, *j)));
                     }
+                    self.s.push(stack_top);
                 }
-                Inst::App => {
-                    let (node, tag) = match self.s.pop() {
-                        StackItem::Closure(clo) => (clo, ProcTag::Closure),
-                        StackItem::Primitive(prim) => (prim, ProcTag::Primitive),
-                        _ => {
-                            unreachable!("apply only closure or primitive object.")
-                        }
-                    };
-                    let lvar = if let StackItem::Other(node) = self.s.pop() {
-                        node
-                    } else {
-                        unreachable!(
-                            "the list of args of closure or primitive is only list object."
-                        )
-                    };
-                    if tag == ProcTag::Primitive {
-                        let result = apply(node, lvar);
-                        if let Node::Error(_) = result {
-                            return result;
-                        } else {
-                            self.s.push(StackItem::new(result, None));
-                        }
-                    } else {
-                        let dump = DumpItem::new(self.s.clone(), self.e.clone(), self.c.clone());
-                        self.d.push(dump);
-                        self.s = StackStack::new();
-                        if let Node::Closure(clo_code, clo_env) = node {
-                            let new_env = Rc::new(RefCell::new(Env::new()));
-                            new_env.borrow_mut().set_next_env(clo_env);
-                            new_env.borrow_mut().set_node(lvar);
-                            self.e = new_env;
-                            self.c = clo_code;
-                        } else {
-                            unreachable!("if ProcTag is Closure, node must be closure object.");
-                        }
-                    }
+                Inst::Gset(sym) => {
+                    let stack_top = self.s.pop();
+                    set_gvar(*sym, stack_top.clone(), global_env);
+                    self.s.push(stack_top);
                 }
+                Inst::App => self.apply_proc(false).map_err(|err| self.error(err))?,
+                Inst::TApp => self.apply_proc(true).map_err(|err| self.error(err))?,
                 Inst::Rtn => {
+                    // 呼び出されたフレームを捨て、呼び出し元のスタックに結果を積む
                     let save = self.d.pop();
-                    let mut s_tmp = self.s.clone();
-                    let result = s_tmp.pop();
-                    if let StackItem::Other(other) = &result {
-                        if let Node::Error(_) = other {
-                            return other.clone();
-                        }
-                    }
-                    self.s = save.stack;
+                    let result = self.s.pop();
+                    self.s.truncate(self.base);
                     self.s.push(result);
+                    self.base = save.base;
                     self.e = save.env;
                     self.c = save.code;
+                    self.pc = save.pc;
                 }
-                Inst::Sel(then_clause, else_clause) => {
-                    if let StackItem::Other(Node::Bool(b)) = self.s.pop() {
-                        self.d.push(DumpItem::new(
-                            StackStack::new(),
-                            Rc::new(RefCell::new(Env::new())),
-                            self.c.clone(),
-                        ));
-                        if b {
-                            self.c = then_clause;
-                        } else {
-                            self.c = else_clause;
-                        }
-                    } else {
-                        self.d.push(DumpItem::new(
-                            StackStack::new(),
-                            Rc::new(RefCell::new(Env::new())),
-                            self.c.clone(),
-                        ));
-                        self.c = then_clause;
+                Inst::Jf(target) => {
+                    if let StackItem::Other(Node::Bool(false)) = self.s.pop() {
+                        self.pc = *target;
                     }
                 }
-                Inst::Join => {
-                    self.c = self.d.pop().code;
-                }
+                Inst::Jmp(target) => self.pc = *target,
                 Inst::Pop => {
                     self.s.pop();
                 }
                 Inst::Args(num) => {
-                    let mut v = Vec::new();
-                    for _ in 0..num {
+                    let mut args = Node::nil();
+                    for _ in 0..*num {
                         match self.s.pop() {
                             StackItem::Other(node)
                             | StackItem::Primitive(node)
-                            | StackItem::Closure(node) => v.insert(0, node),
+                            | StackItem::Closure(node)
+                            | StackItem::Continuation(node) => args = Node::cons(node, args),
                         }
                     }
-                    v.push(Node::nil());
-                    self.s.push(StackItem::new(Node::List(v), None));
+                    self.s.push(StackItem::new(args, None));
                 }
-                Inst::Def(node) => {
-                    if let Node::Ident(ident) = node.clone() {
-                        global_env.insert(ident, self.s.pop());
-                        self.s.push(StackItem::new(node, None));
-                    } else {
-                        unreachable!("opcode `def` treat only ident object.");
-                    }
+                Inst::Def(sym) => {
+                    global_env.insert(*sym, self.s.pop());
+                    self.s.push(StackItem::new(Node::Ident(*sym), None));
                 }
-                Inst::Defm(node) => {
-                    if let Node::Ident(ident) = node.clone() {
-                        if let StackItem::Closure(Node::Closure(code, _)) = self.s.pop() {
-                            global_env.insert(ident, StackItem::new(Node::Macro(code), None));
-                            self.s.push(StackItem::new(node, None));
-                        } else {
-                            panic!();
-                        }
-                    } else {
-                        unreachable!("opcode `defm` treat only ident object.");
+                Inst::Defm(sym) => match self.s.pop() {
+                    StackItem::Closure(Node::Closure(lambda, _)) => {
+                        let mac = StackItem::new(Node::Macro(lambda), None);
+                        global_env.insert(*sym, mac);
+                        self.s.push(StackItem::new(Node::Ident(*sym), None));
                     }
-                }
+                    StackItem::Closure(other)
+                    | StackItem::Primitive(other)
+                    | StackItem::Continuation(other)
+                    | StackItem::Other(other) => {
+                        return Err(self.error(RuntimeError::NotMacro(other.inspect())))
+                    }
+                },
+                Inst::Loc(span) => self.loc = Some(*span),
                 Inst::Stop => match self.s.pop() {
                     StackItem::Primitive(node)
                     | StackItem::Closure(node)
-                    | StackItem::Other(node) => return node,
+                    | StackItem::Continuation(node)
+                    | StackItem::Other(node) => return Ok(node),
                 },
                 // _ => panic!("unimplemented opcode."),
             }
@@ -208,32 +163,162 @@ impl VM {
     }
 }
 
+impl VM {
+    /// 現在の位置を添えて実行時エラーを作る
+    fn error(&self, err: RuntimeError) -> Located<RuntimeError> {
+        Located::new(err, self.loc)
+    }
+
+    /// スタックトップの手続きを引数リストに適用する。
+    /// `tail` が真の場合は末尾呼び出しとして dump にフレームを積まない。
+    fn apply_proc(&mut self, tail: bool) -> Result<(), RuntimeError> {
+        let (node, tag) = match self.s.pop() {
+            StackItem::Closure(clo) => (clo, ProcTag::Closure),
+            StackItem::Primitive(prim) => (prim, ProcTag::Primitive),
+            StackItem::Continuation(cont) => (cont, ProcTag::Continuation),
+            StackItem::Other(other) => return Err(RuntimeError::NotProcedure(other.inspect())),
+        };
+        let lvar = if let StackItem::Other(node) = self.s.pop() {
+            node
+        } else {
+            unreachable!("the list of args of closure or primitive is only list object.")
+        };
+        match tag {
+            ProcTag::Primitive => {
+                if let Node::Primitive(prim) = node {
+                    check_arity(&prim.name, prim.arity, &lvar)?;
+                    match prim.as_control() {
+                        Some(Control::CallCc) => return self.call_cc(lvar, tail),
+                        None => (),
+                    }
+                    let args = match lvar.to_vec() {
+                        Some(args) => args,
+                        None => unreachable!("the list of args of primitive must be proper list."),
+                    };
+                    let result = prim.call(&args)?;
+                    let tag = tag_of(&result);
+                    self.s.push(StackItem::new(result, tag));
+                } else {
+                    unreachable!("if ProcTag is Primitive, node must be primitive object.");
+                }
+            }
+            ProcTag::Closure => {
+                if let Node::Closure(lambda, clo_env) = node {
+                    let name = match lambda.name {
+                        Some(name) => name.to_string(),
+                        None => "#<closure>".to_string(),
+                    };
+                    check_arity(&name, lambda.arity, &lvar)?;
+                    // 呼び出されたフレームは呼び出し元の値の上にスタックを積む。
+                    // 末尾呼び出しでは呼び出し元のフレームを再利用する
+                    if tail {
+                        self.s.truncate(self.base);
+                    } else {
+                        let dump =
+                            DumpItem::new(self.base, self.e.clone(), self.c.clone(), self.pc);
+                        self.d.push(dump);
+                        self.base = self.s.len();
+                    }
+                    let mut new_env = Env::new();
+                    new_env.set_next_env(clo_env);
+                    new_env.set_args(lvar, lambda.arity);
+                    self.e = heap::alloc_env(new_env);
+                    self.c = lambda.code.clone();
+                    self.pc = 0;
+                } else {
+                    unreachable!("if ProcTag is Closure, node must be closure object.");
+                }
+            }
+            ProcTag::Continuation => {
+                if let Node::Continuation(cont) = node {
+                    check_arity("#<continuation>", Arity::Exact(1), &lvar)?;
+                    // 継続の呼び出し時点の状態は捨て、捕捉した時点の状態に戻る
+                    let value = lvar.car().unwrap();
+                    self.s = cont.stack.clone();
+                    self.base = cont.saved.base;
+                    self.e = cont.saved.env.clone();
+                    self.c = cont.saved.code.clone();
+                    self.pc = cont.saved.pc;
+                    self.d = cont.dump.clone();
+                    self.s.push(StackItem::new(value.clone(), tag_of(&value)));
+                } else {
+                    unreachable!("if ProcTag is Continuation, node must be continuation object.");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 現在の s, e, c, d を継続として捕捉し、引数の手続きに渡して呼び出す。
+    fn call_cc(&mut self, lvar: Node, tail: bool) -> Result<(), RuntimeError> {
+        let proc_ = match lvar.to_vec() {
+            Some(args) if args.len() == 1 => args[0].clone(),
+            _ => {
+                return Err(RuntimeError::Primitive(
+                    "call/cc: procedure must be given as only one argument".to_string(),
+                ))
+            }
+        };
+        let tag = match tag_of(&proc_) {
+            Some(tag) => tag,
+            None => {
+                return Err(RuntimeError::Primitive(format!(
+                    "call/cc: argument is not procedure: {}",
+                    proc_.inspect()
+                )))
+            }
+        };
+        // 継続は何度でも再開できるので、スタックはここで複製しておく
+        let saved = DumpItem::new(self.base, self.e.clone(), self.c.clone(), self.pc);
+        let cont = Node::Continuation(Rc::new(ContinuationData {
+            stack: self.s.clone(),
+            saved,
+            dump: self.d.clone(),
+        }));
+        self.s.push(StackItem::new(Node::list(vec![cont]), None));
+        self.s.push(StackItem::new(proc_, Some(tag)));
+        self.apply_proc(tail)
+    }
+}
+
+/// 手続きであれば、その種類を表すタグを返す
+pub fn tag_of(node: &Node) -> Option<ProcTag> {
+    match node {
+        Node::Closure(_, _) => Some(ProcTag::Closure),
+        Node::Primitive(_) => Some(ProcTag::Primitive),
+        Node::Continuation(_) => Some(ProcTag::Continuation),
+        _ => None,
+    }
+}
+
+/// 引数リストの長さが手続きの受け付ける個数に合うか調べる
+fn check_arity(name: &str, arity: Arity, args: &Node) -> Result<(), RuntimeError> {
+    let given = args.list_parts().0.len();
+    if arity.accepts(given) {
+        Ok(())
+    } else {
+        Err(RuntimeError::WrongArgCount {
+            name: name.to_string(),
+            expected: arity,
+            given,
+        })
+    }
+}
+
 fn get_lvar(env: &EnvStack, i: usize, j: isize) -> Option<Node> {
     env.borrow().get(i, j)
 }
 
-fn get_gvar(sym: &str, global_env: &GlobalEnv) -> Option<StackItem> {
-    global_env.get(sym).map(|item| item.clone())
+fn get_gvar(sym: Symbol, global_env: &GlobalEnv) -> Option<StackItem> {
+    global_env.get(sym).cloned()
 }
 
 fn set_lvar(env: &EnvStack, i: usize, j: isize, val: Node) -> Option<()> {
     env.borrow_mut().set(i, j, val)
 }
 
-fn set_gvar(sym: &str, val: StackItem, global_env: &mut GlobalEnv) -> Option<()> {
-    global_env.insert(sym.to_string(), val).map(|_| ())
-}
-
-fn apply(primitive: Node, args: Node) -> Node {
-    if let Node::Primitive(_, proc) = primitive {
-        if let Node::List(args) = args {
-            proc(args)
-        } else {
-            unreachable!("the arg `args` must be Node::List.");
-        }
-    } else {
-        unreachable!("the arg `primitive` must be Node::Primitive.");
-    }
+fn set_gvar(sym: Symbol, val: StackItem, global_env: &mut GlobalEnv) -> Option<()> {
+    global_env.insert(sym, val).map(|_| ())
 }
 
 #[cfg(test)]
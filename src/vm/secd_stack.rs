@@ -1,17 +1,17 @@
 use std::cell::RefCell;
-use std::collections::LinkedList;
 use std::rc::Rc;
 
 use crate::{
     ast::{Node, ProcTag},
     env::Env,
-    inst::Inst,
+    inst::Code,
 };
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum StackItem {
     Closure(Node),
     Primitive(Node),
+    Continuation(Node),
     Other(Node),
 }
 
@@ -21,67 +21,118 @@ impl StackItem {
             match tag {
                 ProcTag::Closure => StackItem::Closure(node),
                 ProcTag::Primitive => StackItem::Primitive(node),
+                ProcTag::Continuation => StackItem::Continuation(node),
             }
         } else {
             StackItem::Other(node)
         }
     }
+
+    pub fn node(&self) -> &Node {
+        match self {
+            StackItem::Closure(node)
+            | StackItem::Primitive(node)
+            | StackItem::Continuation(node)
+            | StackItem::Other(node) => node,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+/// 全てのフレームで共有する値のスタック。各フレームは `base` より上の部分を使う
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct StackStack {
-    stack: LinkedList<StackItem>,
+    stack: Vec<StackItem>,
 }
 
 impl StackStack {
     pub fn new() -> Self {
-        StackStack {
-            stack: LinkedList::new(),
-        }
+        StackStack { stack: Vec::new() }
     }
 
     pub fn push(&mut self, item: StackItem) {
-        self.stack.push_front(item);
+        self.stack.push(item);
     }
 
     pub fn pop(&mut self) -> StackItem {
-        self.stack.pop_front().unwrap()
+        self.stack.pop().unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StackItem> {
+        self.stack.iter()
+    }
+
+    /// フレームを捨て、スタックを `len` 個の値に縮める
+    pub fn truncate(&mut self, len: usize) {
+        self.stack.truncate(len);
     }
 }
 
 pub type EnvStack = Rc<RefCell<Env>>;
-pub type CodeStack = LinkedList<Inst>;
 
-#[derive(Debug, Clone)]
+/// 呼び出し元の状態。スタックはフレームの底の位置、コードは命令列とその中の再開位置で表す
+#[derive(Debug, Clone, PartialEq)]
 pub struct DumpItem {
-    pub stack: StackStack,
+    pub base: usize,
     pub env: EnvStack,
-    pub code: CodeStack,
+    pub code: Code,
+    pub pc: usize,
 }
 
 impl DumpItem {
-    pub fn new(stack: StackStack, env: EnvStack, code: CodeStack) -> Self {
-        DumpItem { stack, env, code }
+    pub fn new(base: usize, env: EnvStack, code: Code, pc: usize) -> Self {
+        DumpItem {
+            base,
+            env,
+            code,
+            pc,
+        }
     }
 }
 
-#[derive(Debug, Clone)]
+/// 継続が捕捉した時点の s と、e, c および dump
+#[derive(Debug, PartialEq)]
+pub struct ContinuationData {
+    pub stack: StackStack,
+    pub saved: DumpItem,
+    pub dump: DumpStack,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct DumpStack {
-    dump: LinkedList<DumpItem>,
+    dump: Vec<DumpItem>,
+    max_depth: usize,
 }
 
 impl DumpStack {
     pub fn new() -> Self {
         DumpStack {
-            dump: LinkedList::new(),
+            dump: Vec::new(),
+            max_depth: 0,
         }
     }
 
     pub fn push(&mut self, dump: DumpItem) {
-        self.dump.push_front(dump);
+        self.dump.push(dump);
+        self.max_depth = self.max_depth.max(self.dump.len());
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DumpItem> {
+        self.dump.iter()
     }
 
     pub fn pop(&mut self) -> DumpItem {
-        self.dump.pop_front().unwrap()
+        self.dump.pop().unwrap()
     }
 }
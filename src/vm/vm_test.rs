@@ -1,5 +1,14 @@
 use super::VM;
-use crate::{ast::Node, compiler::Compiler, env::init_global_env, lexer::Lexer, parser::Parser};
+use crate::{
+    ast::{Arity, Node},
+    compiler::Compiler,
+    env::init_global_env,
+    error::{RuntimeError, TypeError},
+    inst::Inst,
+    lexer::Lexer,
+    parser::Parser,
+    span::{Located, Span},
+};
 
 fn vm_test_template(test_name: &str, source: &str, expected: Node) {
     let lex = Lexer::new(source);
@@ -11,7 +20,7 @@ fn vm_test_template(test_name: &str, source: &str, expected: Node) {
     assert!(result.is_ok(), "{:?}: compiling failed.", test_name);
 
     let mut vm = VM::new(result.unwrap());
-    let rtn_value = vm.run(&mut global_env);
+    let rtn_value = vm.run(&mut global_env).unwrap();
     assert_eq!(
         rtn_value, expected,
         "expected: {:?}, got: {:?}",
@@ -19,6 +28,27 @@ fn vm_test_template(test_name: &str, source: &str, expected: Node) {
     );
 }
 
+fn vm_run(source: &str) -> Result<Node, Located<RuntimeError>> {
+    let node = Parser::new(Lexer::new(source)).parse().unwrap().remove(0);
+    let mut global_env = init_global_env(None);
+    let code = Compiler::new(node).compile(&mut global_env).unwrap();
+    VM::new(code).run(&mut global_env)
+}
+
+/// 評価した値の表記を比べる
+fn vm_inspect_test(source: &str, expected: &str) {
+    let rtn_value = vm_run(source).unwrap();
+    assert_eq!(rtn_value.inspect(), expected, "source: {}", source);
+}
+
+/// 実行時エラーになることを確かめ、そのエラーを返す
+fn vm_error_test(source: &str) -> RuntimeError {
+    match vm_run(source) {
+        Ok(rtn_value) => panic!("source: {}, got: {:?}", source, rtn_value),
+        Err(err) => err.error,
+    }
+}
+
 #[test]
 fn vm_integer_test() {
     let source = "1";
@@ -29,46 +59,39 @@ fn vm_integer_test() {
 #[test]
 fn vm_quote_test() {
     let source = "(quote a)";
-    let expected = Node::Ident("a".to_string());
+    let expected = Node::ident("a");
     vm_test_template("vm_quote_test", source, expected);
 }
 
 #[test]
 fn vm_if_test() {
     let source0 = "(if #t 'a 'b)";
-    let expected0 = Node::Ident("a".to_string());
+    let expected0 = Node::ident("a");
     vm_test_template("vm_if_test (source0)", source0, expected0);
 
     let source1 = "(if #f 'a 'b)";
-    let expected1 = Node::Ident("b".to_string());
+    let expected1 = Node::ident("b");
     vm_test_template("vm_if_test (source1)", source1, expected1);
 }
 
 #[test]
 fn vm_car_test() {
     let source = "(car '(a b c))";
-    let expected = Node::Ident("a".to_string());
+    let expected = Node::ident("a");
     vm_test_template("vm_car_test", source, expected);
 }
 
 #[test]
 fn vm_cdr_test() {
     let source = "(cdr '(a b c))";
-    let expected = Node::List(vec![
-        Node::Ident("b".to_string()),
-        Node::Ident("c".to_string()),
-        Node::nil(),
-    ]);
+    let expected = Node::list(vec![Node::ident("b"), Node::ident("c")]);
     vm_test_template("vm_cdr_test", source, expected);
 }
 
 #[test]
 fn vm_cons_test() {
     let source = "(cons 'a 'b)";
-    let expected = Node::List(vec![
-        Node::Ident("a".to_string()),
-        Node::Ident("b".to_string()),
-    ]);
+    let expected = Node::cons(Node::ident("a"), Node::ident("b"));
     vm_test_template("vm_cons_test", source, expected);
 }
 
@@ -83,6 +106,141 @@ fn vm_eq_test() {
     vm_test_template("vm_eq_test (source1)", source1, expected1);
 }
 
+#[test]
+fn vm_symbol_test() {
+    let cases = [
+        ("(eq? (string->symbol \"abc\") 'abc)", "#t"),
+        ("(symbol->string 'abc)", "\"abc\""),
+        ("(symbol? (string->symbol \"a b\"))", "#t"),
+        ("(symbol? \"abc\")", "#f"),
+        ("(symbol? (gensym))", "#t"),
+        // gensym のシンボルは同じ名前のシンボルとも一致しない
+        (
+            "(let ((g (gensym))) (eq? g (string->symbol (symbol->string g))))",
+            "#f",
+        ),
+        ("(let ((g (gensym))) (eq? g g))", "#t"),
+        ("(eq? (gensym 'tmp) (gensym 'tmp))", "#f"),
+    ];
+    for (source, expected) in cases {
+        vm_inspect_test(source, expected);
+    }
+
+    // gensym で変数の捕捉を避けるマクロ
+    let source = r#"
+(define-macro my-or2
+  (lambda (a b)
+    (let ((tmp (gensym)))
+      (list 'let (list (list tmp a)) (list 'if tmp tmp b)))))
+(define g1 5)
+(let ((tmp 7)) (my-or2 #f tmp))
+"#;
+    vm_multi_test_template("vm_symbol_test (gensym)", source, Node::Int(7));
+}
+
+#[test]
+fn vm_char_test() {
+    let cases = [
+        (r"(char? #\a)", "#t"),
+        (r#"(char? "a")"#, "#f"),
+        (r"(char->integer #\A)", "65"),
+        (r"(char->integer #\あ)", "12354"),
+        ("(integer->char 955)", r"#\λ"),
+        ("(integer->char 32)", r"#\space"),
+        (r"(char=? #\x41 #\A #\A)", "#t"),
+        (r"(char<? #\a #\b #\b)", "#f"),
+        (r"(char-upcase #\a)", r"#\A"),
+        (r"(char-upcase #\ß)", r"#\ß"),
+        (r"(char-downcase #\Λ)", r"#\λ"),
+        (r"(char-alphabetic? #\あ)", "#t"),
+        (r"(char-numeric? #\7)", "#t"),
+        (r"(char-whitespace? #\newline)", "#t"),
+        (r"(eqv? #\a #\a)", "#t"),
+        (r"'(#\a #\( #\x7f)", r"(#\a #\( #\delete)"),
+    ];
+    for (source, expected) in cases {
+        vm_inspect_test(source, expected);
+    }
+
+    let cases = [
+        (
+            "(integer->char 55296)",
+            RuntimeError::OutOfRange {
+                name: "integer->char".to_string(),
+                position: 1,
+                given: "55296".to_string(),
+            },
+        ),
+        (
+            r#"(char<? #\a "b")"#,
+            RuntimeError::WrongType {
+                name: "char<?".to_string(),
+                position: 2,
+                error: TypeError::new("char", &Node::Str("b".to_string())),
+            },
+        ),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(vm_error_test(source), expected, "source: {}", source);
+    }
+}
+
+#[test]
+fn vm_string_test() {
+    let cases = [
+        (r#"(string? "abc")"#, "#t"),
+        (r#"(string-length "日本語abc")"#, "6"),
+        (r#"(string-ref "日本語" 1)"#, r"#\本"),
+        (r#"(substring "こんにちは" 1 3)"#, r#""んに""#),
+        (r#"(substring "こんにちは" 3)"#, r#""ちは""#),
+        (r#"(string-append "あ" "" "いう")"#, r#""あいう""#),
+        (r#"(string=? "あ" "あ" "あ")"#, "#t"),
+        (r#"(string<? "abc" "abd" "あ")"#, "#t"),
+        (r#"(string->list "aあ")"#, r"(#\a #\あ)"),
+        (r#"(list->string (list #\λ #\x))"#, r#""λx""#),
+        (r#"(string-upcase "straße")"#, r#""STRASSE""#),
+        (r#"(string-downcase "ΑΒΓ")"#, r#""αβγ""#),
+        (r#"(string-index "日本語" #\語)"#, "2"),
+        (r#"(string-index "abc" #\z)"#, "#f"),
+        (r#"(string-contains "東京都港区" "都港")"#, "2"),
+        (r#"(string-contains "abc" "d")"#, "#f"),
+        (r#"(string-split "a,,b" #\,)"#, r#"("a" "" "b")"#),
+        (r#"(string-split "  a  b ")"#, r#"("a" "b")"#),
+        (r#"(string-split "a::b" "::")"#, r#"("a" "b")"#),
+        (r#"(string-join '("a" "b" "c") "、")"#, r#""a、b、c""#),
+        (r#"(string-join '("a" "b"))"#, r#""a b""#),
+    ];
+    for (source, expected) in cases {
+        vm_inspect_test(source, expected);
+    }
+
+    // 範囲外の添字や型の合わない引数はエラーにする
+    let out_of_range = |name: &str, position, given: &str| RuntimeError::OutOfRange {
+        name: name.to_string(),
+        position,
+        given: given.to_string(),
+    };
+    let cases = [
+        (r#"(string-ref "あ" 1)"#, out_of_range("string-ref", 2, "1")),
+        (r#"(string-ref "" 0)"#, out_of_range("string-ref", 2, "0")),
+        (
+            r#"(substring "abc" 2 1)"#,
+            out_of_range("substring", 2, "2"),
+        ),
+        (
+            r#"(string-append "a" 'b)"#,
+            RuntimeError::WrongType {
+                name: "string-append".to_string(),
+                position: 2,
+                error: TypeError::new("string", &Node::ident("b")),
+            },
+        ),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(vm_error_test(source), expected, "source: {}", source);
+    }
+}
+
 #[test]
 fn vm_pair_test() {
     let source0 = "(pair? '(a b c))";
@@ -101,3 +259,419 @@ fn vm_pair_test() {
     let expected3 = Node::Bool(false);
     vm_test_template("vm_pair_test (source3)", source3, expected3);
 }
+
+#[test]
+fn vm_tail_call_test() {
+    let source = r#"
+(define (count-down n) (if (= n 0) 'done (count-down (- n 1))))
+(count-down 1000000)
+(reversei '(a b c))
+"#;
+    let expected = vec![
+        Node::ident("count-down"),
+        Node::ident("done"),
+        Node::list(vec![Node::ident("c"), Node::ident("b"), Node::ident("a")]),
+    ];
+
+    let lex = Lexer::new(source);
+    let nodes = Parser::new(lex).parse().unwrap();
+    let mut global_env = init_global_env(None);
+    for (node, expected) in nodes.into_iter().zip(expected) {
+        let code = Compiler::new(node).compile(&mut global_env).unwrap();
+        let mut vm = VM::new(code);
+        let rtn_value = vm.run(&mut global_env).unwrap();
+        assert_eq!(
+            rtn_value, expected,
+            "expected: {:?}, got: {:?}",
+            expected, rtn_value
+        );
+        assert!(
+            vm.max_dump_depth() <= 2,
+            "dump grew in tail calls: {}",
+            vm.max_dump_depth()
+        );
+    }
+}
+
+fn vm_multi_test_template(test_name: &str, source: &str, expected: Node) {
+    let lex = Lexer::new(source);
+    let nodes = Parser::new(lex).parse().unwrap();
+    let mut global_env = init_global_env(None);
+    let mut rtn_value = Node::Undef;
+    for node in nodes {
+        let result = Compiler::new(node).compile(&mut global_env);
+        assert!(result.is_ok(), "{:?}: compiling failed.", test_name);
+        rtn_value = VM::new(result.unwrap()).run(&mut global_env).unwrap();
+    }
+    assert_eq!(
+        rtn_value, expected,
+        "{}: expected: {:?}, got: {:?}",
+        test_name, expected, rtn_value
+    );
+}
+
+#[test]
+fn vm_call_cc_test() {
+    let source0 = "(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))";
+    let expected0 = Node::Int(3);
+    vm_test_template("vm_call_cc_test (source0)", source0, expected0);
+
+    let source1 = "(call-with-current-continuation (lambda (k) 5))";
+    let expected1 = Node::Int(5);
+    vm_test_template("vm_call_cc_test (source1)", source1, expected1);
+
+    // 末尾位置からの call/cc と、大域脱出
+    let source2 = r#"
+(define (find-first pred ls)
+  (call/cc
+    (lambda (return)
+      (for-each-elem (lambda (x) (if (pred x) (return x) #f)) ls)
+      #f)))
+(define (for-each-elem fn ls)
+  (if (null? ls) '() (begin (fn (car ls)) (for-each-elem fn (cdr ls)))))
+(find-first (lambda (x) (< 2 x)) '(1 2 3 4))
+"#;
+    vm_multi_test_template("vm_call_cc_test (source2)", source2, Node::Int(3));
+
+    // 捕捉した継続への再突入
+    let source3 = r#"
+(let ((k #f) (n 0))
+  (let ((v (call/cc (lambda (c) (set! k c) 0))))
+    (set! n (+ n 1))
+    (if (< n 3) (k (+ v 10)) (+ v 100))))
+"#;
+    vm_test_template("vm_call_cc_test (source3)", source3, Node::Int(120));
+
+    // 継続は同じ実体どうしに限り eq? になる。自身を参照する環境があっても中身は比べない
+    vm_inspect_test(
+        "(letrec ((g (lambda () g))) (call/cc (lambda (k) (eq? k k))))",
+        "#t",
+    );
+    vm_inspect_test(
+        "(eq? (call/cc (lambda (k) k)) (call/cc (lambda (k) k)))",
+        "#f",
+    );
+
+    // 再突入しても、捕捉時にスタックに積まれていた値は変わらない
+    let source4 = r#"
+(let ((k #f) (n 0))
+  (let ((r (list 1 (+ 2 (call/cc (lambda (c) (set! k c) 0))))))
+    (set! n (+ n 1))
+    (if (< n 3) (k n) r)))
+"#;
+    let expected4 = Node::list(vec![Node::Int(1), Node::Int(4)]);
+    vm_test_template("vm_call_cc_test (source4)", source4, expected4);
+}
+
+#[test]
+fn vm_rest_args_test() {
+    let cases = [
+        ("((lambda (a b . r) r) 1 2)", "()"),
+        ("((lambda (a . r) (set! r (cons a r)) r) 1 2 3)", "(1 2 3)"),
+        ("((lambda x (set! x 5) x) 1)", "5"),
+        ("((lambda (a . r) (set! a r) (list a r)) 1 2)", "((2) (2))"),
+    ];
+    for (source, expected) in cases {
+        vm_inspect_test(source, expected);
+    }
+}
+
+#[test]
+fn vm_frame_test() {
+    // 呼び出し元の値の上に積んだフレームは、戻るときにその値を残して取り除かれる
+    let source = r#"
+(define (sum ls) (if (null? ls) 0 (+ (car ls) (sum (cdr ls)))))
+(define (weighted ls) (list (sum ls) (* 2 (sum ls)) (sum (cons 10 ls))))
+(weighted '(1 2 3))
+"#;
+    let expected = Node::list(vec![Node::Int(6), Node::Int(12), Node::Int(16)]);
+    vm_multi_test_template("vm_frame_test", source, expected);
+}
+
+#[test]
+fn vm_syntax_rules_test() {
+    // 展開で導入された一時変数が利用者の変数を捕捉しない
+    let source0 = "(let ((x 5)) (or #f x))";
+    vm_test_template("vm_syntax_rules_test (source0)", source0, Node::Int(5));
+
+    // 利用者が局所的に束縛した名前にマクロの導入した識別子が影響されない
+    let source1 = r#"
+(define-syntax my-if
+  (syntax-rules ()
+    ((_ c t e) (cond (c t) (else e)))))
+(let ((else #f) (cond list)) (my-if #f 1 2))
+"#;
+    vm_multi_test_template("vm_syntax_rules_test (source1)", source1, Node::Int(2));
+
+    let source2 = r#"
+(define-syntax swap!
+  (syntax-rules ()
+    ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+(let ((tmp 1) (other 2)) (swap! tmp other) (list tmp other))
+"#;
+    vm_multi_test_template(
+        "vm_syntax_rules_test (source2)",
+        source2,
+        Node::list(vec![Node::Int(2), Node::Int(1)]),
+    );
+
+    let source3 = "(do ((i 0 (+ i 1)) (acc '() (cons i acc))) ((= i 3) acc))";
+    vm_test_template(
+        "vm_syntax_rules_test (source3)",
+        source3,
+        Node::list(vec![Node::Int(2), Node::Int(1), Node::Int(0)]),
+    );
+
+    let source4 = "(cond ((assv 2 '((1 . a) (2 . b))) => cdr) (else 'none))";
+    vm_test_template("vm_syntax_rules_test (source4)", source4, Node::ident("b"));
+}
+
+#[test]
+fn vm_let_syntax_test() {
+    let source0 = r#"
+(let ((x 'outer))
+  (let-syntax ((m (syntax-rules () ((_) x))))
+    (let ((x 'inner))
+      (m))))
+"#;
+    vm_test_template(
+        "vm_let_syntax_test (source0)",
+        source0,
+        Node::ident("outer"),
+    );
+
+    let source1 = r#"
+(letrec-syntax
+    ((my-or (syntax-rules ()
+              ((_) #f)
+              ((_ e) e)
+              ((_ e r ...) (let ((t e)) (if t t (my-or r ...)))))))
+  (let ((t 5))
+    (my-or #f t)))
+"#;
+    vm_test_template("vm_let_syntax_test (source1)", source1, Node::Int(5));
+}
+
+#[test]
+fn vm_set_car_test() {
+    let source0 = "(let ((x (list 1 2 3))) (set-car! (cdr x) 'b) x)";
+    let expected0 = Node::list(vec![Node::Int(1), Node::ident("b"), Node::Int(3)]);
+    vm_test_template("vm_set_car_test (source0)", source0, expected0);
+
+    let source1 = "(let ((x (list 1 2))) (set-cdr! (cdr x) '(3)) x)";
+    let expected1 = Node::list(vec![Node::Int(1), Node::Int(2), Node::Int(3)]);
+    vm_test_template("vm_set_car_test (source1)", source1, expected1);
+
+    // 構造の共有
+    let source2 = "(let* ((x (list 1)) (y (cons 0 x))) (set-car! x 'a) y)";
+    let expected2 = Node::list(vec![Node::Int(0), Node::ident("a")]);
+    vm_test_template("vm_set_car_test (source2)", source2, expected2);
+
+    let source3 = "(let ((x (list 1))) (eq? x (cdr (cons 0 x))))";
+    vm_test_template("vm_set_car_test (source3)", source3, Node::Bool(true));
+
+    let source4 = "(eq? (list 1) (list 1))";
+    vm_test_template("vm_set_car_test (source4)", source4, Node::Bool(false));
+}
+
+#[test]
+fn vm_numeric_tower_test() {
+    let cases = [
+        ("(+ 9223372036854775807 1)", "9223372036854775808"),
+        (
+            "(* 99999999999 99999999999 99999999999)",
+            "999999999970000000000299999999999",
+        ),
+        ("(- (+ 9223372036854775807 1) 1)", "9223372036854775807"),
+        ("(/ 1 3)", "1/3"),
+        ("(+ 1/3 2/3)", "1"),
+        ("(/ 6 4)", "3/2"),
+        ("(+ 1/2 0.5)", "1.0"),
+        ("(* 1.5 2)", "3.0"),
+        ("(- 3)", "-3"),
+        ("(exact->inexact 1/4)", "0.25"),
+        ("(exact 2.5)", "5/2"),
+        ("(inexact 1/8)", "0.125"),
+        ("(floor -3/2)", "-2"),
+        ("(floor 2.7)", "2.0"),
+        ("(round 5/2)", "2"),
+        ("(round 3.5)", "4.0"),
+        ("(sqrt 16)", "4"),
+        ("(sqrt 2.25)", "1.5"),
+        ("(expt 2 100)", "1267650600228229401496703205376"),
+        ("(expt 2 -1)", "1/2"),
+        ("(number->string 255 16)", "\"ff\""),
+        ("(number->string 1/3)", "\"1/3\""),
+        ("(string->number \"1e3\")", "1000.0"),
+        ("(string->number \"101\" 2)", "5"),
+        ("(string->number \"abc\")", "#f"),
+        ("(= 1 1.0 2/2)", "#t"),
+        ("(< 1/3 0.5 1)", "#t"),
+        ("(>= 2 2.0 3)", "#f"),
+        ("(div -7 2)", "-3"),
+        ("(modulo (expt 10 20) 7)", "2"),
+        ("(/ 1.0 0)", "+inf.0"),
+    ];
+    for (source, expected) in cases {
+        vm_inspect_test(source, expected);
+    }
+
+    for source in ["(/ 1 0)", "(div 1 0)", "(+ 1 'a)", "(sqrt -4)"] {
+        vm_error_test(source);
+    }
+
+    // エラーには登録された名前で手続きを示す
+    let wrong_type = |name: &str, position, expected, given: Node| RuntimeError::WrongType {
+        name: name.to_string(),
+        position,
+        error: TypeError::new(expected, &given),
+    };
+    let primitive = |msg: &str| RuntimeError::Primitive(msg.to_string());
+    let cases = [
+        (
+            "(exact->inexact 'a)",
+            wrong_type("exact->inexact", 1, "number", Node::ident("a")),
+        ),
+        (
+            "(inexact 'a)",
+            wrong_type("inexact", 1, "number", Node::ident("a")),
+        ),
+        (
+            "(exact \"1\")",
+            wrong_type("exact", 1, "number", Node::Str("1".to_string())),
+        ),
+        (
+            "(sqrt 'a)",
+            wrong_type("sqrt", 1, "number", Node::ident("a")),
+        ),
+        (
+            "(expt 2 'a)",
+            wrong_type("expt", 2, "number", Node::ident("a")),
+        ),
+        (
+            "(div 7 2.0)",
+            wrong_type("div", 2, "exact integer", Node::Real(2.0)),
+        ),
+        ("(/ 1 0)", primitive("/: division by zero")),
+        ("(modulo 1 0)", primitive("modulo: division by zero: 1 0")),
+        ("(sqrt -4)", primitive("sqrt: negative number: -4")),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(vm_error_test(source), expected, "source: {}", source);
+    }
+}
+
+#[test]
+fn vm_signed_literal_test() {
+    let source0 = "(- -5 +3)";
+    vm_test_template("vm_signed_literal_test (source0)", source0, Node::Int(-8));
+
+    let source1 = "(let ((->x -1)) (+ ->x 007))";
+    vm_test_template("vm_signed_literal_test (source1)", source1, Node::Int(6));
+}
+
+#[test]
+fn vm_location_test() {
+    let source = "(define (f x)\n  (+ x 'a))\n(list 1\n  (f 2))\n(list (car '()) undefined-var)";
+    let expected = [None, Some(Span::new(2, 3)), Some(Span::new(5, 7))];
+
+    let mut p = Parser::new(Lexer::new(source));
+    let nodes = p.parse().unwrap();
+    let spans = p.into_spans();
+    let mut global_env = init_global_env(None);
+    for (node, expected) in nodes.into_iter().zip(expected) {
+        let code = Compiler::with_spans(node, &spans)
+            .compile(&mut global_env)
+            .unwrap();
+        let rtn_value = VM::new(code).run(&mut global_env);
+        if expected.is_some() {
+            assert_eq!(rtn_value.unwrap_err().span, expected);
+        }
+    }
+}
+
+#[test]
+fn vm_runtime_error_test() {
+    let cases = [
+        ("(1 2)", RuntimeError::NotProcedure("1".to_string())),
+        (
+            "(define-macro m 1)",
+            RuntimeError::NotMacro("1".to_string()),
+        ),
+        (
+            "(undefined-proc 1)",
+            RuntimeError::UnboundVariable("undefined-proc".to_string()),
+        ),
+        (
+            "(car 1)",
+            RuntimeError::WrongType {
+                name: "car".to_string(),
+                position: 1,
+                error: TypeError::new("pair", &Node::Int(1)),
+            },
+        ),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(vm_error_test(source), expected, "source: {}", source);
+    }
+}
+
+#[test]
+fn vm_unbound_local_test() {
+    // 存在しない局所変数への代入は実行時エラーになる
+    let code = vec![Inst::Ldc(Node::Int(1)), Inst::Lset(0, 0), Inst::Stop];
+    let mut global_env = init_global_env(None);
+    let err = VM::new(code).run(&mut global_env).unwrap_err();
+    assert_eq!(err.error, RuntimeError::UnboundLocal(0, 0));
+}
+
+#[test]
+fn vm_arity_test() {
+    let wrong = |name: &str, expected, given| RuntimeError::WrongArgCount {
+        name: name.to_string(),
+        expected,
+        given,
+    };
+    let cases = [
+        ("(car)", wrong("car", Arity::Exact(1), 0)),
+        ("(cons 1)", wrong("cons", Arity::Exact(2), 1)),
+        ("(-)", wrong("-", Arity::AtLeast(1), 0)),
+        (
+            "(number->string 1 10 2)",
+            wrong("number->string", Arity::Range(1, 2), 3),
+        ),
+        (
+            "((lambda (x y) y) 1)",
+            wrong("#<closure>", Arity::Exact(2), 1),
+        ),
+        (
+            "((lambda (a b . c) a) 1)",
+            wrong("#<closure>", Arity::AtLeast(2), 1),
+        ),
+        // define で束縛した手続きはその名前で示す
+        (
+            "(begin (define (f x y) y) (f 1))",
+            wrong("f", Arity::Exact(2), 1),
+        ),
+        (
+            "(begin (define g (lambda (x) x)) (g 1 2))",
+            wrong("g", Arity::Exact(1), 2),
+        ),
+        ("(call/cc)", wrong("call/cc", Arity::Exact(1), 0)),
+        (
+            "(call/cc (lambda (k) (k)))",
+            wrong("#<continuation>", Arity::Exact(1), 0),
+        ),
+        (
+            "(call/cc (lambda (k) (k 1 2)))",
+            wrong("#<continuation>", Arity::Exact(1), 2),
+        ),
+    ];
+    for (source, expected) in cases {
+        assert_eq!(vm_error_test(source), expected, "source: {}", source);
+    }
+
+    let source = "((lambda (a . b) (list a b)) 1)";
+    let expected = Node::list(vec![Node::Int(1), Node::nil()]);
+    vm_test_template("vm_arity_test", source, expected);
+}